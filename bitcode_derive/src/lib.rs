@@ -0,0 +1,182 @@
+//! Implementation of `#[derive(Encode, Decode)]` for [bitcode](https://docs.rs/bitcode). Use the
+//! derives re-exported by bitcode instead of depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Generics,
+    Result,
+};
+
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    encode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    decode(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Adds `bound` to every type parameter of `generics`.
+fn add_bound(mut generics: Generics, bound: TokenStream2) -> Generics {
+    for param in &mut generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(#bound));
+        }
+    }
+    generics
+}
+
+/// Returns a pattern that binds references to `fields` to `f0`, `f1`, etc. and those bindings in
+/// order.
+fn destructure(fields: &Fields) -> (TokenStream2, Vec<TokenStream2>) {
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|i| {
+            let binding = format_ident!("f{i}");
+            quote!(#binding)
+        })
+        .collect();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!({ #(#names: ref #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!((#(ref #bindings),*)),
+        Fields::Unit => quote!(),
+    };
+    (pattern, bindings)
+}
+
+/// Returns an expression that decodes `fields` in order, to be prefixed with a path.
+fn construct(fields: &Fields) -> TokenStream2 {
+    let decode = quote!(::bitcode::Decode::decode(reader)?);
+    match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote!({ #(#names: #decode),* })
+        }
+        Fields::Unnamed(unnamed) => {
+            let decodes = unnamed.unnamed.iter().map(|_| &decode);
+            quote!((#(#decodes),*))
+        }
+        Fields::Unit => quote!(),
+    }
+}
+
+/// Returns an expression that decodes `fields` into `path` like bitcode's
+/// [`Deserializer`](https://docs.rs/serde/latest/serde/trait.Deserializer.html), which counts
+/// fields as a level of nesting unless they belong to a unit struct or variant.
+fn decode_fields(fields: &Fields, path: TokenStream2) -> TokenStream2 {
+    match fields {
+        Fields::Unit => quote!(Ok(#path)),
+        _ => {
+            let construct = construct(fields);
+            quote!(::bitcode::__private::nested(reader, |reader| Ok(#path #construct)))
+        }
+    }
+}
+
+fn encode(input: DeriveInput) -> Result<TokenStream2> {
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, bindings) = destructure(&data.fields);
+            quote! {
+                let Self #pattern = *self;
+                #(::bitcode::Encode::encode(#bindings, writer)?;)*
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(i, variant)| {
+                let ident = &variant.ident;
+                let index = i as u32;
+                let (pattern, bindings) = destructure(&variant.fields);
+                quote! {
+                    Self::#ident #pattern => {
+                        ::bitcode::__private::write_variant_index(writer, #index)?;
+                        #(::bitcode::Encode::encode(#bindings, writer)?;)*
+                    }
+                }
+            });
+            // References to empty enums aren't considered empty by match.
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "unions can't derive Encode",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let generics = add_bound(input.generics, quote!(::bitcode::Encode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bitcode::__private::EncodeSealed for #ident #ty_generics #where_clause {}
+
+        impl #impl_generics ::bitcode::Encode for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn encode(
+                &self,
+                writer: &mut impl ::bitcode::__private::Write,
+            ) -> ::core::result::Result<(), ::bitcode::Error> {
+                #body
+                Ok(())
+            }
+        }
+    })
+}
+
+fn decode(input: DeriveInput) -> Result<TokenStream2> {
+    let body = match &input.data {
+        Data::Struct(data) => decode_fields(&data.fields, quote!(Self)),
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(i, variant)| {
+                let ident = &variant.ident;
+                let index = i as u32;
+                let decode = decode_fields(&variant.fields, quote!(Self::#ident));
+                quote!(#index => #decode,)
+            });
+            quote! {
+                match ::bitcode::__private::read_variant_index(reader)? {
+                    #(#arms)*
+                    _ => Err(::bitcode::__private::invalid_variant()),
+                }
+            }
+        }
+        Data::Union(data) => {
+            return Err(Error::new_spanned(
+                data.union_token,
+                "unions can't derive Decode",
+            ))
+        }
+    };
+
+    let ident = &input.ident;
+    let generics = add_bound(input.generics, quote!(::bitcode::Decode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bitcode::__private::DecodeSealed for #ident #ty_generics #where_clause {}
+
+        impl #impl_generics ::bitcode::Decode for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn decode<'__de>(
+                reader: &mut impl ::bitcode::__private::Read<'__de>,
+            ) -> ::core::result::Result<Self, ::bitcode::Error> {
+                #body
+            }
+        }
+    })
+}
@@ -108,6 +108,8 @@ fuzz_target!(|data: &[u8]| {
         i32,
         u64,
         i64,
+        u128,
+        i128,
         usize,
         isize,
         f32,
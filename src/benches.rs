@@ -1,3 +1,4 @@
+use crate::Config;
 use bincode::Options;
 use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
@@ -9,10 +10,12 @@ use rand::prelude::*;
 use rand_chacha::ChaCha20Rng;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::io::Write;
 use std::ops::RangeInclusive;
 use test::{black_box, Bencher};
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "derive", derive(crate::Encode, crate::Decode))]
 struct Data {
     x: Option<f32>,
     y: Option<i8>,
@@ -43,6 +46,7 @@ impl Distribution<Data> for rand::distributions::Standard {
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "derive", derive(crate::Encode, crate::Decode))]
 enum DataEnum {
     #[default]
     Bar,
@@ -81,6 +85,38 @@ fn bitcode_deserialize<T: DeserializeOwned>(v: &[u8]) -> T {
     crate::deserialize(v).unwrap()
 }
 
+fn bitcode_columnar_serialize(v: &(impl Serialize + ?Sized)) -> Vec<u8> {
+    Config::new().with_columnar_layout().serialize(v).unwrap()
+}
+
+fn bitcode_columnar_deserialize<T: DeserializeOwned>(v: &[u8]) -> T {
+    Config::new().with_columnar_layout().deserialize(v).unwrap()
+}
+
+fn bitcode_varint_serialize(v: &(impl Serialize + ?Sized)) -> Vec<u8> {
+    Config::new().with_varint_encoding().serialize(v).unwrap()
+}
+
+fn bitcode_varint_deserialize<T: DeserializeOwned>(v: &[u8]) -> T {
+    Config::new().with_varint_encoding().deserialize(v).unwrap()
+}
+
+#[cfg(feature = "derive")]
+fn bitcode_derive_serialize(v: &(impl crate::Encode + ?Sized)) -> Vec<u8> {
+    crate::encode(v).unwrap()
+}
+
+#[cfg(feature = "derive")]
+fn bitcode_derive_deserialize<T: crate::Decode>(v: &[u8]) -> T {
+    crate::decode(v).unwrap()
+}
+
+fn deflate_best(bytes: &[u8]) -> Vec<u8> {
+    let mut e = DeflateEncoder::new(Vec::new(), Compression::best());
+    e.write_all(bytes).unwrap();
+    e.finish().unwrap()
+}
+
 fn bincode_fixint_serialize(v: &(impl Serialize + ?Sized)) -> Vec<u8> {
     bincode::serialize(v).unwrap()
 }
@@ -181,6 +217,8 @@ macro_rules! bench {
 
 bench!(
     bitcode,
+    bitcode_columnar,
+    bitcode_varint,
     bincode_fixint,
     bincode_varint,
     bincode_lz4,
@@ -189,6 +227,9 @@ bench!(
     postcard
 );
 
+#[cfg(feature = "derive")]
+bench!(bitcode_derive);
+
 #[test]
 fn comparison1() {
     let ref data = random_data(10000);
@@ -198,15 +239,17 @@ fn comparison1() {
         let precision = 2 - (zeros.log10().ceil() as usize).min(1);
 
         println!(
-            "| {name:<22} | {:<12.1} | {zeros:>4.1$}%      |",
+            "| {name:<26} | {:<12.1} | {zeros:>4.1$}%      |",
             b.len() as f32 / data.len() as f32,
             precision,
         );
     };
 
-    println!("| Format                 | Size (bytes) | Zero Bytes |");
-    println!("|------------------------|--------------|------------|");
+    println!("| Format                     | Size (bytes) | Zero Bytes |");
+    println!("|----------------------------|--------------|------------|");
     print_results("Bitcode", bitcode_serialize(data));
+    print_results("Bitcode (Columnar)", bitcode_columnar_serialize(data));
+    print_results("Bitcode (Varint)", bitcode_varint_serialize(data));
     print_results("Bincode", bincode_fixint_serialize(data));
     print_results("Bincode (Varint)", bincode_varint_serialize(data));
 
@@ -220,12 +263,21 @@ fn comparison1() {
         "Bincode (Deflate Best)",
         bincode_flate2_best_serialize(data),
     );
+    print_results("Bitcode (Deflate)", deflate_best(&bitcode_serialize(data)));
+    print_results(
+        "Bitcode (Columnar Deflate)",
+        deflate_best(&bitcode_columnar_serialize(data)),
+    );
+    print_results(
+        "Bitcode (Varint Deflate)",
+        deflate_best(&bitcode_varint_serialize(data)),
+    );
 
     // TODO compressed postcard.
     print_results("Postcard", postcard_serialize(data));
 
     println!(
-        "| ideal (max entropy)    |              | {:.2}%      |",
+        "| ideal (max entropy)        |              | {:.2}%      |",
         100.0 / 255.0
     );
 }
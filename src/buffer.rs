@@ -0,0 +1,237 @@
+use crate::de::read::IoRead;
+use crate::de::read::{ReadWith, ReadWithImpl};
+use crate::de::{deserialize_in, deserialize_prefix_from};
+use crate::nightly::div_ceil;
+use crate::ser::serialize_in;
+use crate::ser::write::WriteWithImpl;
+use crate::{Config, Result, E};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Serializes many values while reusing the same allocation.
+///
+/// Prefer [`serialize`][`crate::serialize`] for serializing a single value.
+///
+/// ```edition2021
+/// let mut encoder = bitcode::Encoder::new();
+/// for i in 0..10u32 {
+///     let encoded: &[u8] = encoder.encode(&vec![i; i as usize]).unwrap();
+///     assert_eq!(encoded, bitcode::serialize(&vec![i; i as usize]).unwrap());
+/// }
+/// ```
+#[derive(Default)]
+pub struct Encoder {
+    data: WriteWithImpl,
+    config: Config,
+}
+
+impl Encoder {
+    /// Creates an empty [`Encoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty [`Encoder`] that serializes with a [`Config`].
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            data: Default::default(),
+            config,
+        }
+    }
+
+    /// Serializes a `T:` [`Serialize`] into a [`&[u8]`][`prim@slice`] that is valid until the
+    /// next call to [`Encoder::encode`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn encode<T>(&mut self, t: &T) -> Result<&[u8]>
+    where
+        T: Serialize + ?Sized,
+    {
+        serialize_in(t, &mut self.data, &self.config)
+    }
+}
+
+/// Deserializes many values while reusing the same allocation.
+///
+/// Prefer [`deserialize`][`crate::deserialize`] for deserializing a single value.
+#[derive(Default)]
+pub struct Decoder {
+    buffer: <ReadWithImpl<'static> as ReadWith<'static>>::Buffer,
+    config: Config,
+}
+
+impl Decoder {
+    /// Creates an empty [`Decoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty [`Decoder`] that deserializes with a [`Config`].
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            buffer: Default::default(),
+            config,
+        }
+    }
+
+    /// Deserializes a [`&[u8]`][`prim@slice`] into an instance of `T:` [`Deserialize`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn decode<'a, T>(&mut self, bytes: &'a [u8]) -> Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        deserialize_in::<T, ReadWithImpl>(bytes, &mut self.buffer, &self.config)
+    }
+}
+
+/// Deserializes values from input that arrives in chunks, such as fragmented packets.
+///
+/// Chunks are added with [`PushDecoder::push`]. [`PushDecoder::decode`] returns
+/// [`Progress::NeedMore`] until a whole value has arrived instead of failing with an eof error.
+/// Values are expected back to back, each as serialized by [`serialize`][`crate::serialize`].
+///
+/// Values aren't framed, so the length of a value is only known once all of it has arrived, and
+/// each call to [`PushDecoder::decode`] that returns [`Progress::NeedMore`] deserializes the value
+/// from its start again. [`Progress::NeedMore`] only tells how many bytes the next read that
+/// failed needs (all of a string or bytes, but only the next element of a sequence), so a large
+/// value that arrives in many small chunks takes time quadratic in its length. Prefer pushing
+/// large chunks or, for streams, framing values with a length prefix such as
+#[cfg_attr(feature = "tokio", doc = "[`BitcodeCodec`][`crate::BitcodeCodec`].")]
+#[cfg_attr(not(feature = "tokio"), doc = "`BitcodeCodec` (requires the `tokio` feature).")]
+///
+/// ```edition2021
+/// use bitcode::{Progress, PushDecoder};
+///
+/// let encoded = bitcode::serialize(&vec!["abc"; 5]).unwrap();
+/// let (a, b) = encoded.split_at(encoded.len() / 2);
+///
+/// let mut decoder = PushDecoder::new();
+/// decoder.push(a);
+/// assert!(matches!(decoder.decode::<Vec<String>>(), Ok(Progress::NeedMore(_))));
+/// decoder.push(b);
+/// assert_eq!(decoder.decode::<Vec<String>>().unwrap(), Progress::Done(vec!["abc".to_owned(); 5]));
+/// ```
+pub struct PushDecoder {
+    bytes: Vec<u8>,
+    /// Index of the first byte of `bytes` that hasn't been deserialized.
+    start: usize,
+    /// Minimum length of `bytes[start..]` before deserializing is worth trying again. Kept
+    /// between pushes so a value is only deserialized again once enough bytes have arrived.
+    needed: usize,
+    max_frame_length: usize,
+    config: Config,
+}
+
+/// The maximum length in bytes of a value of [`PushDecoder`] or a frame of
+/// [`BitcodeCodec`][`crate::BitcodeCodec`] unless another one is set, 8 MiB like tokio_util's
+/// `LengthDelimitedCodec`.
+pub(crate) const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+impl Default for PushDecoder {
+    fn default() -> Self {
+        Self {
+            bytes: vec![],
+            start: 0,
+            needed: 0,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            config: Config::default(),
+        }
+    }
+}
+
+/// The result of [`PushDecoder::decode`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Progress<T> {
+    /// A value was deserialized.
+    Done(T),
+    /// The value isn't complete yet. At least this many more bytes must be pushed before it can be
+    /// deserialized.
+    NeedMore(usize),
+}
+
+impl PushDecoder {
+    /// Creates an empty [`PushDecoder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty [`PushDecoder`] that deserializes with a [`Config`]. A limit set with
+    /// [`Config::with_limit`] applies to each value, so values that would need more bytes than the
+    /// limit are rejected before all of them arrive.
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the maximum length in bytes of a value, 8 MiB by default. Values that need more bytes
+    /// return an [`ErrorKind::LimitExceeded`][`crate::ErrorKind::LimitExceeded`] error instead of
+    /// being buffered, even if the [`Config`] has no limit, so a corrupt length can't make the
+    /// [`PushDecoder`] buffer forever.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Appends a chunk of input.
+    pub fn push(&mut self, chunk: &[u8]) {
+        // Remove values that were already deserialized once they're at least half of the buffer,
+        // so each byte is moved a constant number of times on average.
+        if self.start > self.bytes.len() / 2 {
+            self.bytes.drain(..self.start);
+            self.start = 0;
+        }
+        self.bytes.extend_from_slice(chunk);
+    }
+
+    /// Checks that a value of `len` bytes is within the limits.
+    fn check_len(&self, len: usize) -> Result<()> {
+        if len > self.max_frame_length {
+            return Err(E::LimitExceeded("frame length").e());
+        }
+        self.config.check_limit(len)
+    }
+
+    /// Returns how many bytes have been pushed but not deserialized yet.
+    pub fn pending(&self) -> usize {
+        self.bytes.len() - self.start
+    }
+
+    /// Deserializes the next value if all of it has been pushed. Returns an error if the input is
+    /// invalid, after which the [`PushDecoder`] should be discarded.
+    ///
+    /// Values that haven't fully arrived are deserialized from their start on every call that has
+    /// at least as many bytes as the last [`Progress::NeedMore`] asked for (see [`PushDecoder`]).
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn decode<T>(&mut self) -> Result<Progress<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let pending = self.pending();
+        if pending < self.needed {
+            return Ok(Progress::NeedMore(self.needed - pending));
+        }
+
+        // IoRead returns E::Eof as soon as a read goes past the end, so it knows the minimum
+        // length of the input.
+        let mut r = IoRead::new(&self.bytes[self.start..], None);
+        match deserialize_prefix_from(&mut r, pending, &self.config) {
+            Ok((t, read)) => {
+                self.check_len(read)?;
+                self.start += read;
+                self.needed = 0;
+                Ok(Progress::Done(t))
+            }
+            Err(e) if e.same(&E::Eof.e()) => {
+                let needed = div_ceil(r.bits_needed(), u8::BITS as usize);
+                self.needed = needed.max(pending + 1);
+                self.check_len(self.needed)?;
+                Ok(Progress::NeedMore(self.needed - pending))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
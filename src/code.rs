@@ -0,0 +1,512 @@
+use crate::de::read::{DecodeRead, Read, ReadWith, ReadWithImpl};
+use crate::de::{read_char, read_from, read_gamma};
+use crate::ser::write::Write;
+use crate::ser::write_gamma;
+use crate::{Config, Result, E};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+
+/// A type that can be encoded with [`encode`][`crate::encode`] without going through serde.
+///
+/// Implemented for primitives and most std types. It can't be implemented by hand, so structs and
+/// enums use `#[derive(Encode)]` (requires the `derive` feature), which encodes them like
+/// bitcode's [`Serializer`][`serde::Serializer`] with the default [`Config`][`crate::Config`].
+pub trait Encode: __private::EncodeSealed {
+    #[doc(hidden)]
+    fn encode(&self, writer: &mut impl Write) -> Result<()>;
+}
+
+/// A type that can be decoded with [`decode`][`crate::decode`] without going through serde.
+///
+/// Implemented for primitives and most std types. It can't be implemented by hand, so structs and
+/// enums use `#[derive(Decode)]` (requires the `derive` feature).
+pub trait Decode: Sized + __private::DecodeSealed {
+    #[doc(hidden)]
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self>;
+}
+
+/// Decodes `bytes` with the limits and trailing bytes option of `config`. The other options don't
+/// apply since [`Decode`] always uses the default format.
+pub(crate) fn decode_with<T: Decode>(bytes: &[u8], config: &Config) -> Result<T> {
+    config.check_limit(bytes.len())?;
+    let r = DecodeRead::new(ReadWithImpl::from_inner(bytes), config);
+    read_from(r, config, |r| T::decode(r))
+}
+
+/// Used by `#[derive(Encode, Decode)]`.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::de::read::Read;
+    pub use crate::ser::write::Write;
+    use crate::{Error, Result, E};
+
+    /// Implemented along with [`Encode`][`super::Encode`] by `#[derive(Encode)]`, which is the
+    /// only way to implement it outside of bitcode.
+    pub trait EncodeSealed {}
+
+    /// Implemented along with [`Decode`][`super::Decode`] by `#[derive(Decode)]`, which is the
+    /// only way to implement it outside of bitcode.
+    pub trait DecodeSealed {}
+
+    pub fn write_variant_index(writer: &mut impl Write, variant_index: u32) -> Result<()> {
+        super::write_gamma(writer, variant_index as usize)
+    }
+
+    pub fn read_variant_index<'a>(reader: &mut impl Read<'a>) -> Result<u32> {
+        Ok(super::read_gamma(reader).map_err(|e| e.map_invalid("variant index"))? as u32)
+    }
+
+    pub fn invalid_variant() -> Error {
+        E::Invalid("variant index").e()
+    }
+
+    /// Decodes a nested value with `f`, counting it towards
+    /// [`Config::with_max_depth`][`crate::Config::with_max_depth`] so malicious inputs can't
+    /// overflow the stack.
+    #[inline(always)]
+    pub fn nested<'a, R: Read<'a>, T>(
+        reader: &mut R,
+        f: impl FnOnce(&mut R) -> Result<T>,
+    ) -> Result<T> {
+        reader.enter()?;
+        let v = f(reader)?;
+        reader.exit();
+        Ok(v)
+    }
+}
+
+/// Seals the types with [`Encode`] or [`Decode`] implementations in this file.
+macro_rules! impl_sealed {
+    ($([$($generics:tt)*] $t:ty),* $(,)?) => {
+        $(
+            impl<$($generics)*> __private::EncodeSealed for $t {}
+            impl<$($generics)*> __private::DecodeSealed for $t {}
+        )*
+    };
+}
+
+impl_sealed!(
+    [] u8, [] u16, [] u32, [] u64, [] u128, [] usize,
+    [] i8, [] i16, [] i32, [] i64, [] i128, [] isize,
+    [] bool, [] f32, [] f64, [] char, [] (), [] str, [] String,
+    [T: ?Sized] PhantomData<T>, [T: ?Sized] &T, [T: ?Sized] Box<T>,
+    [T] Option<T>, [T, Er] std::result::Result<T, Er>,
+    [T] [T], [T] Vec<T>, [T] VecDeque<T>, [T, S] HashSet<T, S>, [T] BTreeSet<T>,
+    [K, V, S] HashMap<K, V, S>, [K, V] BTreeMap<K, V>, [T, const N: usize] [T; N],
+);
+
+macro_rules! impl_int {
+    ($($a:ty => $b:ty),*) => {
+        $(
+            impl Encode for $a {
+                #[inline(always)]
+                fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                    writer.write_bits((*self as $b).into(), <$b>::BITS as usize);
+                    Ok(())
+                }
+            }
+
+            impl Decode for $a {
+                #[inline(always)]
+                fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+                    Ok(reader.read_bits(<$b>::BITS as usize)? as $b as $a)
+                }
+            }
+        )*
+    };
+}
+
+// Like serde, usize and isize are encoded as 64 bits.
+impl_int!(
+    u8 => u8, u16 => u16, u32 => u32, u64 => u64, usize => u64,
+    i8 => u8, i16 => u16, i32 => u32, i64 => u64, isize => u64
+);
+
+macro_rules! impl_int128 {
+    ($($a:ty),*) => {
+        $(
+            impl Encode for $a {
+                fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                    let v = *self as u128;
+                    writer.write_bits(v as u64, u64::BITS as usize);
+                    writer.write_bits((v >> u64::BITS) as u64, u64::BITS as usize);
+                    Ok(())
+                }
+            }
+
+            impl Decode for $a {
+                fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+                    let low = reader.read_bits(u64::BITS as usize)?;
+                    let high = reader.read_bits(u64::BITS as usize)?;
+                    Ok((low as u128 | (high as u128) << u64::BITS) as $a)
+                }
+            }
+        )*
+    };
+}
+
+impl_int128!(u128, i128);
+
+impl Encode for bool {
+    #[inline(always)]
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_bit(*self);
+        Ok(())
+    }
+}
+
+impl Decode for bool {
+    #[inline(always)]
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        reader.read_bit()
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.to_bits().encode(writer)
+    }
+}
+
+impl Decode for f32 {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        u32::decode(reader).map(f32::from_bits)
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.to_bits().encode(writer)
+    }
+}
+
+impl Decode for f64 {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        u64::decode(reader).map(f64::from_bits)
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        let mut buf = [0; 4];
+        writer.write_bytes(self.encode_utf8(&mut buf).as_bytes());
+        Ok(())
+    }
+}
+
+impl Decode for char {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        read_char(reader)
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _: &mut impl Write) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    fn decode<'a>(_: &mut impl Read<'a>) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl<T: ?Sized> Encode for PhantomData<T> {
+    fn encode(&self, _: &mut impl Write) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: ?Sized> Decode for PhantomData<T> {
+    fn decode<'a>(_: &mut impl Read<'a>) -> Result<Self> {
+        Ok(PhantomData)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        (**self).encode(writer)
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        T::decode(reader).map(Box::new)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_bit(self.is_some());
+        if let Some(v) = self {
+            v.encode(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        Ok(if reader.read_bit()? {
+            Some(__private::nested(reader, T::decode)?)
+        } else {
+            None
+        })
+    }
+}
+
+impl<T: Encode, Er: Encode> Encode for std::result::Result<T, Er> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        match self {
+            Ok(v) => {
+                __private::write_variant_index(writer, 0)?;
+                v.encode(writer)
+            }
+            Err(e) => {
+                __private::write_variant_index(writer, 1)?;
+                e.encode(writer)
+            }
+        }
+    }
+}
+
+impl<T: Decode, Er: Decode> Decode for std::result::Result<T, Er> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        match __private::read_variant_index(reader)? {
+            0 => Ok(Ok(__private::nested(reader, T::decode)?)),
+            1 => Ok(Err(__private::nested(reader, Er::decode)?)),
+            _ => Err(__private::invalid_variant()),
+        }
+    }
+}
+
+/// Writes the length of a string or bytes, checking it like
+/// [`Serializer::serialize_bytes`][`serde::Serializer::serialize_bytes`].
+fn encode_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    if bytes.len() > isize::MAX as usize / u8::BITS as usize {
+        return Err(E::NotSupported("bytes.len() must be < isize::MAX / u8::BITS").e());
+    }
+    write_gamma(writer, bytes.len())?;
+    writer.write_bytes(bytes);
+    Ok(())
+}
+
+impl Encode for str {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_bytes(writer, self.as_bytes())
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.as_str().encode(writer)
+    }
+}
+
+impl Decode for String {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        let len = read_gamma(reader)?;
+        if len > isize::MAX as usize / u8::MAX as usize {
+            return Err(E::Invalid("length").e());
+        }
+        reader.allocate(len)?;
+        // Readers check that there are at least len bytes left before allocating.
+        String::from_utf8(reader.read_bytes(len)?).map_err(|_| E::Invalid("utf8").e())
+    }
+}
+
+/// Encodes the length and elements of a sequence.
+fn encode_seq(
+    writer: &mut impl Write,
+    len: usize,
+    items: impl IntoIterator<Item = impl Encode>,
+) -> Result<()> {
+    write_gamma(writer, len)?;
+    items.into_iter().try_for_each(|v| v.encode(writer))
+}
+
+/// Reads the length of a sequence or map, counting 1 byte per element towards the allocation
+/// limit like [`Deserializer`][`serde::Deserializer`] does.
+fn decode_seq_len<'a>(reader: &mut impl Read<'a>) -> Result<usize> {
+    let len = read_gamma(reader)?;
+    reader.allocate(len)?;
+    Ok(len)
+}
+
+/// Decodes an element of a sequence whose length already counted 1 byte for it.
+fn decode_element<'a, T: Decode>(reader: &mut impl Read<'a>) -> Result<T> {
+    reader.allocate(std::mem::size_of::<T>().saturating_sub(1))?;
+    T::decode(reader)
+}
+
+/// Decodes the length and elements of a sequence.
+fn decode_seq<'a, T: Decode, C: FromIterator<T>>(reader: &mut impl Read<'a>) -> Result<C> {
+    __private::nested(reader, |reader| {
+        let len = decode_seq_len(reader)?;
+        // Collecting a Result doesn't trust the size hint, so len isn't trusted with the
+        // allocation.
+        (0..len).map(|_| decode_element(reader)).collect()
+    })
+}
+
+/// Decodes the length and entries of a map. Unlike tuples, entries aren't a level of nesting.
+fn decode_map<'a, K: Decode, V: Decode, C: FromIterator<(K, V)>>(
+    reader: &mut impl Read<'a>,
+) -> Result<C> {
+    __private::nested(reader, |reader| {
+        let len = decode_seq_len(reader)?;
+        (0..len)
+            .map(|_| {
+                let k = decode_element(reader)?;
+                reader.allocate(std::mem::size_of::<V>())?;
+                Ok((k, V::decode(reader)?))
+            })
+            .collect()
+    })
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(writer, self.len(), self)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.as_slice().encode(writer)
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        __private::nested(reader, |reader| {
+            let len = decode_seq_len(reader)?;
+            // Like serde, don't trust len with more than 1 MiB up front.
+            let max = 1024 * 1024 / std::mem::size_of::<T>().max(1);
+            let mut vec = Vec::with_capacity(len.min(max));
+            for _ in 0..len {
+                vec.push(decode_element(reader)?);
+            }
+            Ok(vec)
+        })
+    }
+}
+
+impl<T: Encode> Encode for VecDeque<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(writer, self.len(), self)
+    }
+}
+
+impl<T: Decode> Decode for VecDeque<T> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        decode_seq(reader)
+    }
+}
+
+impl<T: Encode, S> Encode for HashSet<T, S> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(writer, self.len(), self)
+    }
+}
+
+impl<T: Decode + Eq + Hash, S: BuildHasher + Default> Decode for HashSet<T, S> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        decode_seq(reader)
+    }
+}
+
+impl<T: Encode> Encode for BTreeSet<T> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(writer, self.len(), self)
+    }
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        decode_seq(reader)
+    }
+}
+
+impl<K: Encode, V: Encode, S> Encode for HashMap<K, V, S> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(writer, self.len(), self)
+    }
+}
+
+impl<K: Decode + Eq + Hash, V: Decode, S: BuildHasher + Default> Decode for HashMap<K, V, S> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        decode_map(reader)
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        encode_seq(writer, self.len(), self)
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        decode_map(reader)
+    }
+}
+
+// Arrays and tuples have a known length so it isn't encoded.
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, writer: &mut impl Write) -> Result<()> {
+        self.iter().try_for_each(|v| v.encode(writer))
+    }
+}
+
+impl<T: Decode, const N: usize> Decode for [T; N] {
+    fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+        let vec: Vec<T> = __private::nested(reader, |reader| {
+            (0..N).map(|_| T::decode(reader)).collect::<Result<_>>()
+        })?;
+        Ok(vec
+            .try_into()
+            .unwrap_or_else(|_| unreachable!("vec has N elements")))
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl_sealed!([$($name),+] ($($name,)+));
+
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            fn encode(&self, writer: &mut impl Write) -> Result<()> {
+                $(self.$index.encode(writer)?;)+
+                Ok(())
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            fn decode<'a>(reader: &mut impl Read<'a>) -> Result<Self> {
+                __private::nested(reader, |reader| Ok(($($name::decode(reader)?,)+)))
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
@@ -0,0 +1,111 @@
+use crate::buffer::DEFAULT_MAX_FRAME_LENGTH;
+use crate::de::deserialize_in;
+use crate::de::{deserialize_len_prefix, read::ReadWith, read::ReadWithImpl};
+use crate::ser::write::WriteWithImpl;
+use crate::ser::{serialize_in, serialize_len_prefix, MAX_LEN_PREFIX};
+use crate::{Config, Error, E};
+use bytes::{Buf, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// A [`tokio_util::codec`] [`Encoder`][`tokio_util::codec::Encoder`] and
+/// [`Decoder`][`tokio_util::codec::Decoder`] for `T`.
+///
+/// Each value is prefixed with its length in bytes, which is encoded like the length of a
+/// sequence (see [`Config::with_varint_lengths`]) and padded to a byte.
+///
+/// Requires the `tokio` feature.
+pub struct BitcodeCodec<T> {
+    encoder: WriteWithImpl,
+    decoder: <ReadWithImpl<'static> as ReadWith<'static>>::Buffer,
+    max_frame_length: usize,
+    config: Config,
+    _spooky: PhantomData<fn(T) -> T>,
+}
+
+impl<T> Default for BitcodeCodec<T> {
+    fn default() -> Self {
+        Self::with_config(Config::default())
+    }
+}
+
+impl<T> BitcodeCodec<T> {
+    /// Creates a [`BitcodeCodec`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a [`BitcodeCodec`] that (de)serializes with a [`Config`]. A limit set with
+    /// [`Config::with_limit`] applies to each value, so longer frames are rejected before they
+    /// are buffered.
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            encoder: Default::default(),
+            decoder: Default::default(),
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            config,
+            _spooky: PhantomData,
+        }
+    }
+
+    /// Sets the maximum length in bytes of a frame, 8 MiB by default like tokio_util's
+    /// `LengthDelimitedCodec`. Longer frames return an
+    /// [`ErrorKind::LimitExceeded`][`crate::ErrorKind::LimitExceeded`] error instead of being
+    /// buffered, even if the [`Config`] has no limit.
+    pub fn with_max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+}
+
+impl<T: Serialize> tokio_util::codec::Encoder<T> for BitcodeCodec<T> {
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let bytes = serialize_in(&item, &mut self.encoder, &self.config)?;
+
+        // Write the prefix straight into dst and then cut off the bytes it didn't use.
+        let start = dst.len();
+        dst.reserve(MAX_LEN_PREFIX + bytes.len());
+        dst.resize(start + MAX_LEN_PREFIX, 0);
+        let prefix = serialize_len_prefix(bytes.len(), &mut dst[start..], &self.config);
+        dst.truncate(start + *prefix.as_ref().unwrap_or(&0));
+        prefix?;
+
+        dst.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> tokio_util::codec::Decoder for BitcodeCodec<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Only the prefix is needed to know the length of the frame.
+        let prefix_bytes = &src[..src.len().min(MAX_LEN_PREFIX)];
+        let (len, prefix) = match deserialize_len_prefix(prefix_bytes, &self.config) {
+            Ok(v) => v,
+            Err(e) if e.same(&E::Eof.e()) && prefix_bytes.len() < MAX_LEN_PREFIX => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        if len > self.max_frame_length {
+            return Err(E::LimitExceeded("frame length").e());
+        }
+        self.config.check_limit(len)?;
+
+        let end = prefix
+            .checked_add(len)
+            .ok_or(E::LimitExceeded("frame length").e())?;
+        let Some(frame) = src.get(prefix..end) else {
+            src.reserve(end - src.len());
+            return Ok(None);
+        };
+        let t = deserialize_in::<T, ReadWithImpl>(frame, &mut self.decoder, &self.config)?;
+        src.advance(end);
+        Ok(Some(t))
+    }
+}
@@ -0,0 +1,437 @@
+use crate::code::decode_with;
+use crate::de::{
+    deserialize_from_reader_with, deserialize_prefix_with, deserialize_versioned_with,
+    deserialize_with, read::ReadWithImpl,
+};
+use crate::ser::{
+    serialize_into_slice_with, serialize_into_writer_with, serialize_versioned_with,
+    serialize_with, serialized_size_bits_with, write::WriteWithImpl,
+};
+use crate::{Decode, Result, E};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Options for (de)serialization, similar to bincode's
+/// [`Options`](https://docs.rs/bincode/latest/bincode/config/trait.Options.html).
+///
+/// Options that change the format are integer encoding, length encoding, string alignment,
+/// layout, variant counts, skippable fields and the self-describing encoding.
+/// Values must be deserialized with the same format options that serialized them.
+///
+/// ```edition2021
+/// let config = bitcode::Config::new()
+///     .with_varint_encoding()
+///     .with_aligned_strings()
+///     .with_limit(1024);
+///
+/// let encoded: Vec<u8> = config.serialize(&(42u64, "abc")).unwrap();
+/// let decoded: (u64, &str) = config.deserialize(&encoded).unwrap();
+/// assert_eq!(decoded, (42, "abc"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub(crate) aligned_strings: bool,
+    pub(crate) varint: bool,
+    pub(crate) varint_lengths: bool,
+    pub(crate) columnar: bool,
+    pub(crate) self_describing: bool,
+    pub(crate) skippable_fields: bool,
+    /// Enums registered with [`Config::with_variant_count`], shared between clones.
+    pub(crate) variant_counts: Arc<[(&'static str, u32)]>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) allocation_limit: Option<usize>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) allow_trailing: bool,
+}
+
+impl Config {
+    /// Creates the default [`Config`], which is the one used by [`serialize`][`crate::serialize`]
+    /// and [`deserialize`][`crate::deserialize`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pads strings and byte arrays to the next byte. This costs up to 7 bits per string but
+    /// allows `&str` and `&[u8]` to borrow from the input instead of being copied.
+    ///
+    /// `&[u8]` must be serialized with [`Serializer::serialize_bytes`][`serde::Serializer::serialize_bytes`]
+    /// since the [`Serialize`] implementation of `[u8]` serializes a sequence.
+    ///
+    /// Sequences and maps of unknown length are not supported with this option.
+    pub fn with_aligned_strings(mut self) -> Self {
+        self.aligned_strings = true;
+        self
+    }
+
+    /// Writes integers wider than 8 bits with a variable number of bits, so small values take
+    /// fewer bits. A value with `n` significant bits takes `n - 1` bits plus the bits of the
+    /// [gamma](https://en.wikipedia.org/wiki/Elias_gamma_coding) encoded `n + 1`, e.g. 1 bit for
+    /// 0, 13 bits for 100 and 76 bits for [`u64::MAX`]. Signed integers are
+    /// [zigzag](https://en.wikipedia.org/wiki/Variable-length_quantity#Zigzag_encoding) encoded.
+    pub fn with_varint_encoding(mut self) -> Self {
+        self.varint = true;
+        self
+    }
+
+    /// Writes integers with all their bits (the default).
+    pub fn with_fixint_encoding(mut self) -> Self {
+        self.varint = false;
+        self
+    }
+
+    /// Writes lengths of strings, sequences and maps like integers in
+    /// [`Config::with_varint_encoding`]. Takes more bits than the default for lengths below 7 but
+    /// fewer bits for lengths above 13.
+    pub fn with_varint_lengths(mut self) -> Self {
+        self.varint_lengths = true;
+        self
+    }
+
+    /// Writes lengths of strings, sequences and maps with
+    /// [gamma](https://en.wikipedia.org/wiki/Elias_gamma_coding) encoding (the default).
+    pub fn with_gamma_lengths(mut self) -> Self {
+        self.varint_lengths = false;
+        self
+    }
+
+    /// Stores sequences and maps column by column instead of element by element. Each part of the
+    /// elements (e.g. a field of a struct or the fields of an enum variant) is stored in its own
+    /// column, one column after the other, so similar values end up next to each other. This
+    /// makes the output more compressible, but (de)serialization slower. Strings in a sequence
+    /// are never borrowed with [`Config::with_aligned_strings`].
+    ///
+    /// Sequences and maps of unknown length are not supported with this option.
+    pub fn with_columnar_layout(mut self) -> Self {
+        self.columnar = true;
+        self
+    }
+
+    /// Stores sequences and maps element by element (the default).
+    pub fn with_row_layout(mut self) -> Self {
+        self.columnar = false;
+        self
+    }
+
+    /// Writes a 5 bit tag with the type of each value before it, so values can be deserialized
+    /// without knowing their type. This supports [`Deserializer::deserialize_any`], which
+    /// `#[serde(untagged)]`, `#[serde(tag = "...")]`, `#[serde(flatten)]` and types such as
+    /// [`serde::de::IgnoredAny`] rely on.
+    ///
+    /// Tuples also take their number of fields and structs take their number of fields and the
+    /// name of each field. Struct fields are deserialized by name, so fields can be reordered and
+    /// unknown fields are skipped. Enum variants are read as maps with a single entry from the
+    /// variant index to its fields by `deserialize_any`. Their variant indices are gamma encoded
+    /// regardless of [`Config::with_variant_count`].
+    ///
+    /// ```edition2021
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    /// #[serde(untagged)]
+    /// enum Id {
+    ///     Number(u64),
+    ///     Name(String),
+    /// }
+    ///
+    /// let config = bitcode::Config::new().with_self_describing_encoding();
+    /// let ids = vec![Id::Number(5), Id::Name("five".to_owned())];
+    ///
+    /// let encoded = config.serialize(&ids).unwrap();
+    /// assert_eq!(config.deserialize::<Vec<Id>>(&encoded).unwrap(), ids);
+    /// assert!(bitcode::deserialize::<Vec<Id>>(&bitcode::serialize(&ids).unwrap()).is_err());
+    /// ```
+    ///
+    /// [`Deserializer::deserialize_any`]: serde::Deserializer::deserialize_any
+    pub fn with_self_describing_encoding(mut self) -> Self {
+        self.self_describing = true;
+        self
+    }
+
+    /// Writes values without their types (the default).
+    pub fn with_tagless_encoding(mut self) -> Self {
+        self.self_describing = false;
+        self
+    }
+
+    /// Writes the number of fields of each struct that weren't skipped and a bit before each of
+    /// them, so fields skipped with `#[serde(skip_serializing_if = "...")]` take at most 1 bit
+    /// instead of returning an error. Absent fields are deserialized as [`None`] if they are
+    /// [`Option`]s and from their default if they have `#[serde(default)]`.
+    ///
+    /// A present field is preceded by a 1 bit and a 0 bit for each field skipped since the
+    /// previous present one, so skipped fields at the end take no bits. Fields are matched by
+    /// their index rather than their name, so `#[serde(alias = "...")]` works but a field with
+    /// only one of `#[serde(skip_serializing)]` and `#[serde(skip_deserializing)]` shifts the
+    /// ones after it. The self-describing encoding (see
+    /// [`Config::with_self_describing_encoding`]) supports skipped fields without this option.
+    ///
+    /// ```edition2021
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    /// struct User {
+    ///     id: u32,
+    ///     #[serde(skip_serializing_if = "Option::is_none")]
+    ///     email: Option<String>,
+    /// }
+    ///
+    /// let config = bitcode::Config::new().with_skippable_fields();
+    /// let user = User { id: 5, email: None };
+    /// assert_eq!(config.serialized_size_bits(&user).unwrap(), 36);
+    ///
+    /// let encoded = config.serialize(&user).unwrap();
+    /// assert_eq!(config.deserialize::<User>(&encoded).unwrap(), user);
+    /// assert!(bitcode::serialize(&user).is_err());
+    /// ```
+    pub fn with_skippable_fields(mut self) -> Self {
+        self.skippable_fields = true;
+        self
+    }
+
+    /// Writes struct fields without presence bits (the default). Skipping a field returns an
+    /// error unless [`Config::with_self_describing_encoding`] is set.
+    pub fn with_no_skippable_fields(mut self) -> Self {
+        self.skippable_fields = false;
+        self
+    }
+
+    /// Writes the variant indices of the enum called `name` with a fixed number of bits,
+    /// `ceil(log2(count))`, instead of gamma encoding them. E.g. every variant of a 9 variant enum
+    /// takes 4 bits instead of 1 to 7 bits depending on its index.
+    ///
+    /// `name` is the name the enum is serialized with (see `#[serde(rename)]`) and `count` must be
+    /// its number of variants. Deserializing an enum with a different number of variants returns
+    /// an error. Enums that aren't registered are gamma encoded.
+    ///
+    /// Enums are only known by their names while (de)serializing, so all enums called `name` are
+    /// registered at once. Enums with the same name and a different number of variants should be
+    /// renamed (or not registered) since they can't share a `count`.
+    ///
+    /// ```edition2021
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    /// enum Dir {
+    ///     N, NE, E, SE, S, SW, W, NW,
+    /// }
+    ///
+    /// let config = bitcode::Config::new().with_variant_count("Dir", 8);
+    /// assert_eq!(config.serialized_size_bits(&Dir::NW).unwrap(), 3);
+    ///
+    /// let encoded = config.serialize(&Dir::NW).unwrap();
+    /// assert_eq!(config.deserialize::<Dir>(&encoded).unwrap(), Dir::NW);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` was already registered with a different `count`.
+    pub fn with_variant_count(mut self, name: &'static str, count: u32) -> Self {
+        if let Some(registered) = self.variant_count(name) {
+            assert!(
+                registered == count,
+                "enum {name:?} is already registered with {registered} variants"
+            );
+            return self;
+        }
+        self.variant_counts = self
+            .variant_counts
+            .iter()
+            .copied()
+            .chain([(name, count)])
+            .collect();
+        self
+    }
+
+    /// Returns the number of variants registered for the enum called `name`.
+    pub(crate) fn variant_count(&self, name: &str) -> Option<u32> {
+        self.variant_counts
+            .iter()
+            .find(|&&(n, _)| n == name)
+            .map(|&(_, count)| count)
+    }
+
+    /// Returns an error instead of serializing more than `limit` bytes or deserializing an input
+    /// longer than `limit` bytes.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Removes the limit set by [`Config::with_limit`] (the default).
+    pub fn with_no_limit(mut self) -> Self {
+        self.limit = None;
+        self
+    }
+
+    /// Returns an error instead of deserializing values that allocate more than `limit` bytes.
+    /// Each byte of a string or byte array counts as 1 and each element of a sequence or map
+    /// counts as its size in memory, e.g. a `Vec<String>` of 2 strings of 3 bytes counts as
+    /// `2 * size_of::<String>() + 6`. Lengths are checked before anything is allocated for them,
+    /// so untrusted inputs can't make the deserializer allocate large amounts of memory. Serde
+    /// preallocates at most 1 MiB for a sequence or map before its elements are counted.
+    ///
+    /// Regardless of this option, strings and byte arrays longer than the rest of the input are
+    /// rejected before being allocated.
+    pub fn with_allocation_limit(mut self, limit: usize) -> Self {
+        self.allocation_limit = Some(limit);
+        self
+    }
+
+    /// Removes the limit set by [`Config::with_allocation_limit`] (the default).
+    pub fn with_no_allocation_limit(mut self) -> Self {
+        self.allocation_limit = None;
+        self
+    }
+
+    /// Returns an error instead of (de)serializing values nested more than `depth` levels deep.
+    /// Options, sequences, tuples, maps, structs and enum variants with fields each add a level.
+    ///
+    /// Recommended for deserializing untrusted inputs into recursive types, which could otherwise
+    /// overflow the stack.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Removes the limit set by [`Config::with_max_depth`] (the default).
+    pub fn with_no_max_depth(mut self) -> Self {
+        self.max_depth = None;
+        self
+    }
+
+    /// Ignores bytes left over after deserializing a value instead of returning an error.
+    pub fn allow_trailing_bytes(mut self) -> Self {
+        self.allow_trailing = true;
+        self
+    }
+
+    /// Returns an error if bytes are left over after deserializing a value (the default).
+    pub fn reject_trailing_bytes(mut self) -> Self {
+        self.allow_trailing = false;
+        self
+    }
+
+    /// Checks that `bytes` is within the limit set by [`Config::with_limit`].
+    pub(crate) fn check_limit(&self, bytes: usize) -> Result<()> {
+        if self.limit.is_some_and(|limit| bytes > limit) {
+            Err(E::LimitExceeded("size").e())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Serializes a `T:` [`Serialize`] into a [`Vec<u8>`] with this [`Config`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn serialize<T>(&self, t: &T) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        serialize_with::<WriteWithImpl>(t, self)
+    }
+
+    /// Returns the size of `T:` [`Serialize`] in bits with this [`Config`] without serializing it.
+    /// See [`serialized_size_bits`][`crate::serialized_size_bits`].
+    pub fn serialized_size_bits<T>(&self, t: &T) -> Result<usize>
+    where
+        T: Serialize + ?Sized,
+    {
+        serialized_size_bits_with(t, self)
+    }
+
+    /// Serializes a `T:` [`Serialize`] into the start of a [`&mut [u8]`][`prim@slice`] with this
+    /// [`Config`] and returns how many bytes were written. See
+    /// [`serialize_into_slice`][`crate::serialize_into_slice`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn serialize_into_slice<T>(&self, t: &T, slice: &mut [u8]) -> Result<usize>
+    where
+        T: Serialize + ?Sized,
+    {
+        serialize_into_slice_with(t, slice, self)
+    }
+
+    /// Serializes a `T:` [`Serialize`] into an [`std::io::Write`] with this [`Config`]. See
+    /// [`serialize_into_writer`][`crate::serialize_into_writer`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn serialize_into_writer<T>(&self, w: impl std::io::Write, t: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        serialize_into_writer_with(t, w, self)
+    }
+
+    /// Serializes a `T:` [`Serialize`] into a [`Vec<u8>`] prefixed with
+    /// [`FORMAT_VERSION`][`crate::FORMAT_VERSION`] and `schema_version` with this [`Config`]. See
+    /// [`serialize_versioned`][`crate::serialize_versioned`].
+    ///
+    /// The prefix doesn't record the options of this [`Config`], so the value must still be
+    /// deserialized with the same format options.
+    pub fn serialize_versioned<T>(&self, t: &T, schema_version: u32) -> Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+    {
+        serialize_versioned_with::<WriteWithImpl>(t, schema_version, self)
+    }
+
+    /// Deserializes a [`&[u8]`][`prim@slice`] serialized by [`Config::serialize_versioned`] into
+    /// an instance of `T:` [`Deserialize`] with this [`Config`]. See
+    /// [`deserialize_versioned`][`crate::deserialize_versioned`].
+    pub fn deserialize_versioned<'a, T>(&self, bytes: &'a [u8], schema_version: u32) -> Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        deserialize_versioned_with::<T, ReadWithImpl>(bytes, schema_version, self)
+    }
+
+    /// Deserializes a [`&[u8]`][`prim@slice`] into an instance of `T:` [`Deserialize`] with this
+    /// [`Config`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn deserialize<'a, T>(&self, bytes: &'a [u8]) -> Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        deserialize_with::<T, ReadWithImpl>(bytes, self)
+    }
+
+    /// Deserializes the start of a [`&[u8]`][`prim@slice`] into an instance of `T:`
+    /// [`Deserialize`] with this [`Config`] and returns the bytes after it. See
+    /// [`deserialize_prefix`][`crate::deserialize_prefix`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn deserialize_prefix<'a, T>(&self, bytes: &'a [u8]) -> Result<(T, &'a [u8])>
+    where
+        T: Deserialize<'a>,
+    {
+        deserialize_prefix_with::<T, ReadWithImpl>(bytes, self)
+    }
+
+    /// Deserializes an [`std::io::Read`] into an instance of `T:` [`DeserializeOwned`] with this
+    /// [`Config`]. See [`deserialize_from_reader`][`crate::deserialize_from_reader`].
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn deserialize_from_reader<T>(&self, r: impl std::io::Read) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        deserialize_from_reader_with(r, self)
+    }
+
+    /// Decodes a [`&[u8]`][`prim@slice`] into an instance of `T:` [`Decode`] with the limits of
+    /// this [`Config`]. See [`decode`][`crate::decode`].
+    ///
+    /// [`Decode`] always uses the default format, so only [`Config::with_limit`],
+    /// [`Config::with_allocation_limit`], [`Config::with_max_depth`] and
+    /// [`Config::allow_trailing_bytes`] apply.
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn decode<T>(&self, bytes: &[u8]) -> Result<T>
+    where
+        T: Decode,
+    {
+        decode_with(bytes, self)
+    }
+}
@@ -1,37 +1,248 @@
-use crate::nightly::utf8_char_width;
-use crate::{Error, Result, E};
+use crate::fixed_bits::{bits_int_bits, fits};
+use crate::nightly::{div_ceil, utf8_char_width};
+use crate::ser::{variant_index_bits, variant_slot};
+use crate::tag::{Tag, BITS_LEN_BITS};
+use crate::{Config, Error, Result, E, FORMAT_VERSION};
+use serde::de::DeserializeOwned;
 use serde::de::{
-    DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
+    DeserializeSeed, EnumAccess, IgnoredAny, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
 };
 use serde::{Deserialize, Deserializer};
+use std::borrow::Cow;
 
 pub(crate) mod read;
-use read::{Read, ReadWith};
+use read::{Column, ColumnsRead, IoRead, Read, ReadWith};
 
 pub(crate) fn deserialize_with<'a, T: Deserialize<'a>, R: ReadWith<'a>>(
     bytes: &'a [u8],
+    config: &Config,
 ) -> Result<T> {
-    deserialize_from(R::from_inner(bytes))
+    config.check_limit(bytes.len())?;
+    deserialize_from(R::from_inner(bytes), config)
 }
 
-pub(crate) fn deserialize_from<'a, T: Deserialize<'a>>(r: impl Read) -> Result<T> {
-    let mut d = BitcodeDeserializer { data: r };
-    let result = T::deserialize(&mut d);
+/// Like [`deserialize_with`] but first checks the versions written by
+/// [`serialize_versioned_with`][`crate::ser::serialize_versioned_with`].
+pub(crate) fn deserialize_versioned_with<'a, T: Deserialize<'a>, R: ReadWith<'a>>(
+    bytes: &'a [u8],
+    schema_version: u32,
+    config: &Config,
+) -> Result<T> {
+    config.check_limit(bytes.len())?;
+    read_from(R::from_inner(bytes), config, |r| {
+        read_version(r, FORMAT_VERSION, "format")?;
+        read_version(r, schema_version, "schema")?;
+        T::deserialize(&mut BitcodeDeserializer::new(r, config))
+    })
+}
+
+/// Reads a version and returns [`E::VersionMismatch`] if it isn't `expected`.
+fn read_version<'a>(r: &mut impl Read<'a>, expected: u32, name: &'static str) -> Result<()> {
+    if r.read_bits(u32::BITS as usize)? != expected as u64 {
+        return Err(E::VersionMismatch(name).e());
+    }
+    Ok(())
+}
 
-    let r = d.data.finish();
-    if let Err(e) = &r {
-        if e.same(&E::Eof.e()) {
-            return Err(E::Eof.e());
+/// Like [`deserialize_with`] but reuses `buffer`'s allocation.
+pub(crate) fn deserialize_in<'a, T: Deserialize<'a>, R: ReadWith<'a>>(
+    bytes: &'a [u8],
+    buffer: &mut R::Buffer,
+    config: &Config,
+) -> Result<T> {
+    config.check_limit(bytes.len())?;
+    let mut r = R::from_inner_in(bytes, std::mem::take(buffer));
+    let result = deserialize_from(&mut r, config);
+    *buffer = r.into_buffer();
+    result
+}
+
+/// Like [`deserialize_with`] but reads from an [`std::io::Read`] as it goes.
+pub(crate) fn deserialize_from_reader_with<T: DeserializeOwned>(
+    r: impl std::io::Read,
+    config: &Config,
+) -> Result<T> {
+    deserialize_from(IoRead::new(r, config.limit), config)
+}
+
+/// Like [`deserialize_with`] but only deserializes the value at the start of `bytes` and returns
+/// the bytes after it.
+pub(crate) fn deserialize_prefix_with<'a, T: Deserialize<'a>, R: ReadWith<'a>>(
+    bytes: &'a [u8],
+    config: &Config,
+) -> Result<(T, &'a [u8])> {
+    // Only wrap the start of bytes and double it while the value needs more, so deserializing k
+    // values from the front of a long input doesn't wrap all of it k times.
+    let mut len = PREFIX_WINDOW.min(bytes.len());
+    loop {
+        let prefix = &bytes[..len];
+        match deserialize_prefix_from(R::from_inner(prefix), len, config) {
+            Err(e) if e.same(&E::Eof.e()) && len < bytes.len() => {
+                len = len.saturating_mul(2).min(bytes.len());
+            }
+            result => {
+                let (t, read) = result?;
+                return Ok((t, &bytes[read..]));
+            }
         }
     }
+}
+
+/// How many bytes [`deserialize_prefix_with`] tries first.
+const PREFIX_WINDOW: usize = 256;
+
+/// Like [`deserialize_from`] but only deserializes the next value of an input of `len` bytes
+/// without checking what comes after it.
+pub(crate) fn deserialize_partial<'a, T: Deserialize<'a>>(
+    r: impl Read<'a>,
+    len: usize,
+    config: &Config,
+) -> Result<T> {
+    read_partial(r, len, config, |d| T::deserialize(d))
+}
 
-    let t = result?;
-    r?;
-    Ok(t)
+/// Reads from an input of `len` bytes with `f`, reporting reads past its end as [`E::Eof`].
+fn read_partial<'a, R: Read<'a>, T>(
+    mut r: R,
+    len: usize,
+    config: &Config,
+    f: impl FnOnce(&mut BitcodeDeserializer<&mut R>) -> Result<T>,
+) -> Result<T> {
+    let result = f(&mut BitcodeDeserializer::new(&mut r, config));
+    let bits_read = r.bits_read();
+
+    // Readers may read zeros past the end of the input instead of returning E::Eof.
+    if bits_read > len * u8::BITS as usize || result.as_ref().is_err_and(|e| e.same(&E::Eof.e())) {
+        return Err(E::Eof.e().at_bit(bits_read));
+    }
+    result.map_err(|e| e.at_bit(bits_read))
+}
+
+/// Reads a length written by [`serialize_len_prefix`][`crate::ser::serialize_len_prefix`] from
+/// the start of `bytes`. Returns the length and how many bytes it took up.
+#[cfg(feature = "tokio")]
+pub(crate) fn deserialize_len_prefix(bytes: &[u8], config: &Config) -> Result<(usize, usize)> {
+    let mut r = read::ReadWithImpl::from_inner(bytes);
+    let len = read_partial(&mut r, bytes.len(), config, |d| d.read_len())?;
+    r.align_to_byte().map_err(|e| e.at_bit(r.bits_read()))?;
+    Ok((len, r.bits_read() / u8::BITS as usize))
+}
+
+/// Like [`deserialize_partial`] but also returns how many bytes the value took up. The padding
+/// bits of its last byte must be zero.
+pub(crate) fn deserialize_prefix_from<'a, T: Deserialize<'a>>(
+    mut r: impl Read<'a>,
+    len: usize,
+    config: &Config,
+) -> Result<(T, usize)> {
+    let t = deserialize_partial(&mut r, len, config)?;
+    r.align_to_byte().map_err(|e| e.at_bit(r.bits_read()))?;
+
+    let bytes_read = r.bits_read() / u8::BITS as usize;
+    config.check_limit(bytes_read)?;
+    Ok((t, bytes_read))
+}
+
+pub(crate) fn deserialize_from<'a, T: Deserialize<'a>>(
+    r: impl Read<'a>,
+    config: &Config,
+) -> Result<T> {
+    read_from(r, config, |r| {
+        T::deserialize(&mut BitcodeDeserializer::new(r, config))
+    })
+}
+
+/// Reads all of `r` with `f`, reporting reads past its end as [`E::Eof`].
+pub(crate) fn read_from<'a, R: Read<'a>, T>(
+    mut r: R,
+    config: &Config,
+    f: impl FnOnce(&mut R) -> Result<T>,
+) -> Result<T> {
+    let result = f(&mut r).map_err(|e| e.at_bit(r.bits_read()));
+
+    // Errors caused by reading past the end of the input are reported as E::Eof.
+    match finish(&mut r, config) {
+        Err(e) if e.same(&E::Eof.e()) => Err(e),
+        finished => {
+            let t = result?;
+            finished?;
+            Ok(t)
+        }
+    }
+}
+
+/// Checks that all of `r` was read, unless [`Config::allow_trailing_bytes`] is set.
+pub(crate) fn finish<'a>(r: &mut impl Read<'a>, config: &Config) -> Result<()> {
+    match r.finish() {
+        Err(e) if config.allow_trailing && e.same(&E::ExpectedEof.e()) => Ok(()),
+        finished => finished.map_err(|e| e.at_bit(r.bits_read())),
+    }
+}
+
+/// Reads a length written by [`write_gamma`][`crate::ser::write_gamma`].
+#[inline(always)]
+pub(crate) fn read_gamma<'a>(r: &mut impl Read<'a>) -> Result<usize> {
+    let max_zeros = (usize::BITS - 1) as usize;
+    let zeros = r
+        .read_zeros(max_zeros)
+        .map_err(|e| e.map_invalid("length"))?;
+
+    let integer_bits = zeros + 1;
+    let v = r.read_bits(integer_bits)?;
+
+    let lz = u64::BITS as usize - integer_bits;
+    let v = (v << lz).reverse_bits() as u64;
+
+    // Gamma can't encode 0 so sub 1 (see write_gamma for more details).
+    Ok((v - 1) as usize)
+}
+
+/// Reads a [`char`] written as UTF-8.
+pub(crate) fn read_char<'a>(r: &mut impl Read<'a>) -> Result<char> {
+    let mut buf = [0; 4];
+    buf[0] = r.read_bits(u8::BITS as usize)? as u8;
+
+    let len = utf8_char_width(buf[0]);
+    if len > 1 {
+        let bits = r.read_bits((len - 1) * u8::BITS as usize)?;
+        buf[1..len].copy_from_slice(&bits.to_le_bytes()[0..len - 1]);
+    }
+
+    let s = std::str::from_utf8(&buf[..len]).map_err(|_| E::Invalid("char").e())?;
+    debug_assert_eq!(s.as_bytes().len(), len);
+    debug_assert_eq!(s.chars().count(), 1);
+    Ok(s.chars().next().unwrap())
 }
 
 struct BitcodeDeserializer<R> {
     data: R,
+    config: Config,
+    /// Remaining bytes before [`Config::with_allocation_limit`] is exceeded.
+    allocation_budget: usize,
+    /// Remaining levels of nesting before [`Config::with_max_depth`] is exceeded.
+    depth_budget: usize,
+    /// If `data` is the [`ColumnsRead`] of a sequence with [`Config::with_columnar_layout`].
+    in_columns: bool,
+    /// The number of bits of the next [`u64`] and of the integer it's deserialized into if it's
+    /// deserialized by [`bits`][`crate::bits`].
+    bits: Option<(usize, usize)>,
+    /// The tag of the next value if it was already read (see [`Self::visit_tag`]).
+    tag: Option<Tag>,
+}
+
+impl<R> BitcodeDeserializer<R> {
+    fn new(data: R, config: &Config) -> Self {
+        Self {
+            data,
+            config: config.clone(),
+            allocation_budget: config.allocation_limit.unwrap_or(usize::MAX),
+            depth_budget: config.max_depth.unwrap_or(usize::MAX),
+            in_columns: false,
+            bits: None,
+            tag: None,
+        }
+    }
 }
 
 macro_rules! read_int {
@@ -42,90 +253,476 @@ macro_rules! read_int {
     };
 }
 
-impl<R: Read> BitcodeDeserializer<R> {
+macro_rules! read_varint {
+    ($name:ident, $fixed:ident, $a:ty) => {
+        fn $name(&mut self) -> Result<$a> {
+            if self.config.varint {
+                self.read_varint(<$a>::BITS as usize).map(|v| v as $a)
+            } else {
+                self.$fixed()
+            }
+        }
+    };
+}
+
+impl<'de, R: Read<'de>> BitcodeDeserializer<R> {
     read_int!(read_u8, u8);
     read_int!(read_u16, u16);
     read_int!(read_u32, u32);
     read_int!(read_u64, u64);
 
+    fn read_u128(&mut self) -> Result<u128> {
+        if self.config.varint {
+            return self.read_varint_u128();
+        }
+        let low = self.read_u64()?;
+        let high = self.read_u64()?;
+        Ok(low as u128 | (high as u128) << u64::BITS)
+    }
+
+    read_varint!(read_var_u16, read_u16, u16);
+    read_varint!(read_var_u32, read_u32, u32);
+    read_varint!(read_var_u64, read_u64, u64);
+
+    /// Reads a `v` written by `serialize_varint` that has up to `max_bits` significant bits.
+    fn read_varint(&mut self, max_bits: usize) -> Result<u64> {
+        let bits = self.read_gamma().map_err(|e| e.map_invalid("varint"))?;
+        if bits > max_bits {
+            return Err(E::Invalid("varint").e());
+        }
+        Ok(match bits {
+            0 => 0,
+            1 => 1,
+            _ => self.data.read_bits(bits - 1)? | (1 << (bits - 1)),
+        })
+    }
+
+    fn read_varint_u128(&mut self) -> Result<u128> {
+        let bits = self.read_gamma().map_err(|e| e.map_invalid("varint"))?;
+        if bits <= u64::BITS as usize {
+            return Ok(match bits {
+                0 => 0,
+                1 => 1,
+                _ => (self.data.read_bits(bits - 1)? | (1 << (bits - 1))) as u128,
+            });
+        } else if bits > u128::BITS as usize {
+            return Err(E::Invalid("varint").e());
+        }
+
+        let low = self.data.read_bits(u64::BITS as usize)?;
+        let high_bits = bits - u64::BITS as usize;
+        let high = match high_bits {
+            1 => 1,
+            _ => self.data.read_bits(high_bits - 1)? | (1 << (high_bits - 1)),
+        };
+        Ok(low as u128 | (high as u128) << u64::BITS)
+    }
+
     fn read_bool(&mut self) -> Result<bool> {
         self.data.read_bit()
     }
 
     fn read_len(&mut self) -> Result<usize> {
-        let max_zeros = (usize::BITS - 1) as usize;
-        let zeros = self
-            .data
-            .read_zeros(max_zeros)
-            .map_err(|e| e.map_invalid("length"))?;
+        if self.config.varint_lengths {
+            self.read_varint(usize::BITS as usize)
+                .map(|v| v as usize)
+                .map_err(|e| e.map_invalid("length"))
+        } else {
+            self.read_gamma()
+        }
+    }
 
-        let integer_bits = zeros + 1;
-        let v = self.data.read_bits(integer_bits)?;
+    fn read_gamma(&mut self) -> Result<usize> {
+        read_gamma(&mut self.data)
+    }
 
-        let lz = u64::BITS as usize - integer_bits;
-        let v = (v << lz).reverse_bits() as u64;
+    /// Subtracts `len` from the allocation budget. Must be called before allocating so malicious
+    /// inputs can't allocate more memory than allowed.
+    fn allocate(&mut self, len: usize) -> Result<()> {
+        self.allocation_budget = self
+            .allocation_budget
+            .checked_sub(len)
+            .ok_or(E::LimitExceeded("allocation").e())?;
+        Ok(())
+    }
 
-        // Gamma can't encode 0 so sub 1 (see serialize_len for more details).
-        Ok((v - 1) as usize)
+    /// Counts the memory taken by an element (or a key or value of a map entry) of type `T`
+    /// towards the allocation budget. The length of its sequence already counted 1 byte for it.
+    fn allocate_element<T>(&mut self) -> Result<()> {
+        self.allocate(std::mem::size_of::<T>().saturating_sub(1))
     }
 
-    #[inline(never)] // Removing this makes bench_bitcode_deserialize 27% slower.
-    fn read_len_and_bytes(&mut self) -> Result<Vec<u8>> {
+    /// Deserializes a value nested at `slot` (see [`Read::enter_path`]) with `f`, counting it
+    /// towards [`Config::with_max_depth`] so malicious inputs can't overflow the stack.
+    fn nested<T>(&mut self, slot: u64, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.depth_budget = self
+            .depth_budget
+            .checked_sub(1)
+            .ok_or(E::LimitExceeded("depth").e())?;
+        self.data.enter_path(slot);
+        let v = f(self)?;
+        self.data.exit_path();
+        self.depth_budget += 1;
+        Ok(v)
+    }
+
+    /// Deserializes `len` items nested at `slot` with `visitor`. The items of tuples and structs
+    /// each have their own path unlike the elements of sequences. Items that `visitor` doesn't
+    /// visit are skipped with [`Config::with_self_describing_encoding`].
+    fn visit_items<V>(
+        &mut self,
+        len: usize,
+        slot: u64,
+        next_path: bool,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.nested(slot, |d| {
+            let mut items = SeqItems {
+                deserializer: d,
+                len,
+                next_path,
+            };
+            let v = visitor.visit_seq(&mut items)?;
+            if items.deserializer.config.self_describing {
+                while items.next_element::<IgnoredAny>()?.is_some() {}
+            }
+            Ok(v)
+        })
+    }
+
+    /// Reads the columns written after the length of a sequence or map with
+    /// [`Config::with_columnar_layout`] and deserializes its elements from them with `f`.
+    fn read_columns<T>(
+        &mut self,
+        f: impl FnOnce(&mut BitcodeDeserializer<ColumnsRead>) -> Result<T>,
+    ) -> Result<T> {
+        let count = self.read_len()?;
+        // Each column takes up its length and a Column, even though its length might only take up
+        // 1 bit of the input.
+        let column_size = std::mem::size_of::<usize>() + std::mem::size_of::<Column>();
+        self.allocate(count.saturating_mul(column_size))?;
+        let lens: Vec<usize> = (0..count).map(|_| self.read_len()).collect::<Result<_>>()?;
+        let columns = lens
+            .into_iter()
+            .map(|len| {
+                self.allocate(div_ceil(len, u64::BITS as usize) * std::mem::size_of::<u64>())?;
+                Column::read_from(&mut self.data, len)
+            })
+            .collect::<Result<_>>()?;
+
+        let mut d = BitcodeDeserializer {
+            data: ColumnsRead::new(columns),
+            config: self.config.clone(),
+            allocation_budget: self.allocation_budget,
+            depth_budget: self.depth_budget,
+            in_columns: true,
+            bits: None,
+            tag: None,
+        };
+        // Report errors at their position in the input rather than in their column.
+        let v = f(&mut d).map_err(|e| e.at_bit(d.data.position()))?;
+        d.data.finish().map_err(|e| e.at_bit(d.data.position()))?;
+        self.allocation_budget = d.allocation_budget;
+        Ok(v)
+    }
+
+    /// Reads the length of a sequence or map.
+    fn read_seq_len(&mut self) -> Result<usize> {
+        let len = self.read_len()?;
+        self.allocate(len)?;
+        Ok(len)
+    }
+
+    /// Reads the length of a string or bytes. Readers check that there are at least `len` bytes
+    /// left before allocating.
+    fn read_bytes_len(&mut self) -> Result<usize> {
         let len = self.read_len()?;
         if len > isize::MAX as usize / u8::MAX as usize {
             return Err(E::Invalid("length").e());
         }
+        self.allocate(len)?;
+        Ok(len)
+    }
+
+    #[inline(never)] // Removing this makes bench_bitcode_deserialize 27% slower.
+    fn read_len_and_bytes(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_bytes_len()?;
+        if self.config.aligned_strings {
+            self.data.align_to_byte()?;
+            return self.data.read_aligned_bytes(len).map(Cow::into_owned);
+        }
         self.data.read_bytes(len)
     }
 
-    fn read_variant_index(&mut self) -> Result<u32> {
-        Ok(self
-            .read_len()
-            .map_err(|e| e.map_invalid("variant index"))? as u32)
+    /// Like [`Self::read_len_and_bytes`] but borrows the bytes if the reader supports it. Requires
+    /// [`Config::with_aligned_strings`].
+    fn read_len_and_aligned_bytes(&mut self) -> Result<Cow<'de, [u8]>> {
+        debug_assert!(self.config.aligned_strings);
+        let len = self.read_bytes_len()?;
+        self.data.align_to_byte()?;
+        self.data.read_aligned_bytes(len)
+    }
+
+    /// Returns the tag of the next value, reading it unless [`Self::visit_tag`] already did.
+    fn take_tag(&mut self) -> Result<Tag> {
+        match self.tag.take() {
+            Some(tag) => Ok(tag),
+            None => Tag::read(&mut self.data),
+        }
+    }
+
+    /// Makes the next `deserialize_*` call use `tag` instead of reading one.
+    fn with_tag(&mut self, tag: Tag) -> &mut Self {
+        self.tag = Some(tag);
+        self
+    }
+
+    /// Deserializes the value described by `tag` with `visitor`, like `deserialize_any`.
+    fn visit_tag<V>(&mut self, tag: Tag, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match tag {
+            Tag::Unit => self.with_tag(tag).deserialize_unit(visitor),
+            Tag::Bool => self.with_tag(tag).deserialize_bool(visitor),
+            Tag::I8 => self.with_tag(tag).deserialize_i8(visitor),
+            Tag::I16 => self.with_tag(tag).deserialize_i16(visitor),
+            Tag::I32 => self.with_tag(tag).deserialize_i32(visitor),
+            Tag::I64 => self.with_tag(tag).deserialize_i64(visitor),
+            Tag::I128 => self.with_tag(tag).deserialize_i128(visitor),
+            Tag::U8 => self.with_tag(tag).deserialize_u8(visitor),
+            Tag::U16 => self.with_tag(tag).deserialize_u16(visitor),
+            Tag::U32 => self.with_tag(tag).deserialize_u32(visitor),
+            Tag::U64 => self.with_tag(tag).deserialize_u64(visitor),
+            Tag::U128 => self.with_tag(tag).deserialize_u128(visitor),
+            Tag::F32 => self.with_tag(tag).deserialize_f32(visitor),
+            Tag::F64 => self.with_tag(tag).deserialize_f64(visitor),
+            Tag::Char => self.with_tag(tag).deserialize_char(visitor),
+            Tag::Str => self.with_tag(tag).deserialize_str(visitor),
+            Tag::Bytes => self.with_tag(tag).deserialize_bytes(visitor),
+            Tag::None | Tag::Some => self.with_tag(tag).deserialize_option(visitor),
+            Tag::NewtypeStruct => self.with_tag(tag).deserialize_newtype_struct("", visitor),
+            Tag::Seq => self.with_tag(tag).deserialize_seq(visitor),
+            Tag::Map => self.with_tag(tag).deserialize_map(visitor),
+            Tag::Tuple => {
+                let len = self.read_seq_len()?;
+                self.visit_items(len, 0, true, visitor)
+            }
+            Tag::Struct => self.visit_named_fields(0, visitor),
+            Tag::UnitVariant | Tag::NewtypeVariant | Tag::TupleVariant | Tag::StructVariant => {
+                let index = self.read_tagged_variant_index()?;
+                visitor.visit_map(VariantMap {
+                    deserializer: self,
+                    tag,
+                    index: Some(index),
+                })
+            }
+            Tag::Bits => {
+                let bits = self.read_bits_len()?;
+                self.bits = Some((bits, u64::BITS as usize));
+                self.visit_items(1, 0, true, visitor)
+            }
+        }
+    }
+
+    /// Returns the number of fields of a tuple, which is only written with
+    /// [`Config::with_self_describing_encoding`] and is otherwise `len`.
+    fn read_tuple_len(&mut self, len: usize) -> Result<usize> {
+        if self.config.self_describing {
+            self.read_seq_len()
+        } else {
+            Ok(len)
+        }
+    }
+
+    /// Deserializes the fields of a [`Tag::Struct`] or [`Tag::StructVariant`] nested at `slot` with
+    /// `visitor`. They're visited as a map from their names to their values, so fields that
+    /// `visitor` doesn't know about are skipped.
+    fn visit_named_fields<V>(&mut self, slot: u64, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_seq_len()?;
+        self.nested(slot, |d| {
+            let mut fields = NamedFields {
+                deserializer: d,
+                len,
+            };
+            let v = visitor.visit_map(&mut fields)?;
+            while fields.next_key::<IgnoredAny>()?.is_some() {
+                fields.next_value::<IgnoredAny>()?;
+            }
+            Ok(v)
+        })
+    }
+
+    /// Deserializes the fields of a struct written with [`Config::with_skippable_fields`] nested
+    /// at `slot` with `visitor`. They're visited as a map from the indices of the present fields
+    /// to their values, so `visitor` fills in the absent ones.
+    fn visit_present_fields<V>(&mut self, slot: u64, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let len = self.read_seq_len()?;
+        self.nested(slot, |d| {
+            let mut fields = PresentFields {
+                deserializer: d,
+                len,
+                index: 0,
+            };
+            let v = visitor.visit_map(&mut fields)?;
+            while fields.next_key::<IgnoredAny>()?.is_some() {
+                fields.next_value::<IgnoredAny>()?;
+            }
+            Ok(v)
+        })
+    }
+
+    /// Reads the number of bits of a [`Tag::Bits`] integer.
+    fn read_bits_len(&mut self) -> Result<usize> {
+        let bits = self.data.read_bits(BITS_LEN_BITS)? as usize;
+        if bits > u64::BITS as usize {
+            return Err(E::Invalid("bits").e());
+        }
+        Ok(bits)
+    }
+
+    /// Reads the variant index written after a variant [`Tag`].
+    fn read_tagged_variant_index(&mut self) -> Result<u32> {
+        let index = self
+            .read_gamma()
+            .map_err(|e| e.map_invalid("variant index"))?;
+        u32::try_from(index).map_err(|_| E::Invalid("variant index").e())
     }
+
+    /// Reads the variant index of the enum called `name`, which has `variants` variants.
+    fn read_variant_index(&mut self, name: &str, variants: usize) -> Result<u32> {
+        let Some(count) = self.config.variant_count(name) else {
+            return Ok(self
+                .read_gamma()
+                .map_err(|e| e.map_invalid("variant index"))? as u32);
+        };
+        if count as usize != variants {
+            return Err(E::Invalid("variant count").e());
+        }
+
+        let index = match variant_index_bits(variants) {
+            0 => 0,
+            bits => self.data.read_bits(bits)?,
+        };
+        if index >= variants as u64 {
+            return Err(E::Invalid("variant index").e());
+        }
+        Ok(index as u32)
+    }
+}
+
+/// Reads the tag of the next value if [`Config::with_self_describing_encoding`] is set. Continues
+/// if it matches one of the tags, otherwise returns the value deserialized by
+/// [`BitcodeDeserializer::visit_tag`].
+macro_rules! expect_tag {
+    ($self:ident, $visitor:ident, $($tag:ident)|+) => {
+        if $self.config.self_describing {
+            match $self.take_tag()? {
+                $(Tag::$tag)|+ => (),
+                tag => return $self.visit_tag(tag, $visitor),
+            }
+        }
+    };
 }
 
 macro_rules! deserialize_int {
-    ($name:ident, $visit:ident, $read:ident, $a:ty) => {
+    ($name:ident, $visit:ident, $read:ident, $a:ty, $tag:ident) => {
         fn $name<V>(self, visitor: V) -> Result<V::Value>
         where
             V: Visitor<'de>,
         {
+            expect_tag!(self, visitor, $tag);
             visitor.$visit(self.$read()? as $a)
         }
     };
 }
 
-impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
+macro_rules! deserialize_zigzag {
+    ($name:ident, $visit:ident, $read:ident, $a:ty, $tag:ident) => {
+        fn $name<V>(self, visitor: V) -> Result<V::Value>
+        where
+            V: Visitor<'de>,
+        {
+            expect_tag!(self, visitor, $tag);
+            let v = self.$read()?;
+            visitor.$visit(if self.config.varint {
+                (v >> 1) as $a ^ -((v & 1) as $a)
+            } else {
+                v as $a
+            })
+        }
+    };
+}
+
+impl<'de, R: Read<'de>> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     type Error = Error;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        return Err(E::NotSupported("deserialize_any").e());
+        if !self.config.self_describing {
+            return Err(E::NotSupported("deserialize_any").e());
+        }
+        if self.bits.is_some() {
+            // The integer in a bits tuple struct doesn't have a tag.
+            return self.deserialize_u64(visitor);
+        }
+        let tag = self.take_tag()?;
+        self.visit_tag(tag, visitor)
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, Bool);
         visitor.visit_bool(self.read_bool()?)
     }
 
-    deserialize_int!(deserialize_i8, visit_i8, read_u8, i8);
-    deserialize_int!(deserialize_i16, visit_i16, read_u16, i16);
-    deserialize_int!(deserialize_i32, visit_i32, read_u32, i32);
-    deserialize_int!(deserialize_i64, visit_i64, read_u64, i64);
-    deserialize_int!(deserialize_u8, visit_u8, read_u8, u8);
-    deserialize_int!(deserialize_u16, visit_u16, read_u16, u16);
-    deserialize_int!(deserialize_u32, visit_u32, read_u32, u32);
-    deserialize_int!(deserialize_u64, visit_u64, read_u64, u64);
+    deserialize_int!(deserialize_i8, visit_i8, read_u8, i8, I8);
+    deserialize_zigzag!(deserialize_i16, visit_i16, read_var_u16, i16, I16);
+    deserialize_zigzag!(deserialize_i32, visit_i32, read_var_u32, i32, I32);
+    deserialize_zigzag!(deserialize_i64, visit_i64, read_var_u64, i64, I64);
+    deserialize_int!(deserialize_u8, visit_u8, read_u8, u8, U8);
+    deserialize_int!(deserialize_u16, visit_u16, read_var_u16, u16, U16);
+    deserialize_int!(deserialize_u32, visit_u32, read_var_u32, u32, U32);
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if let Some((bits, max_bits)) = self.bits.take() {
+            let v = if bits == 0 {
+                0
+            } else {
+                self.data.read_bits(bits)?
+            };
+            if !fits(v, max_bits) {
+                return Err(E::Invalid("bits").e());
+            }
+            return visitor.visit_u64(v);
+        }
+        expect_tag!(self, visitor, U64);
+        visitor.visit_u64(self.read_var_u64()?)
+    }
+    deserialize_zigzag!(deserialize_i128, visit_i128, read_u128, i128, I128);
+    deserialize_int!(deserialize_u128, visit_u128, read_u128, u128, U128);
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, F32);
         visitor.visit_f32(f32::from_bits(self.read_u32()?))
     }
 
@@ -133,6 +730,7 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, F64);
         visitor.visit_f64(f64::from_bits(self.read_u64()?))
     }
 
@@ -140,32 +738,33 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
-        let mut buf = [0; 4];
-        buf[0] = self.read_u8()?;
-
-        let len = utf8_char_width(buf[0]);
-        if len > 1 {
-            let bits = self.data.read_bits((len - 1) * u8::BITS as usize)?;
-            buf[1..len].copy_from_slice(&bits.to_le_bytes()[0..len - 1]);
-        }
-
-        let s = std::str::from_utf8(&buf[..len]).map_err(|_| E::Invalid("char").e())?;
-        debug_assert_eq!(s.as_bytes().len(), len);
-        debug_assert_eq!(s.chars().count(), 1);
-        visitor.visit_char(s.chars().next().unwrap())
+        expect_tag!(self, visitor, Char);
+        visitor.visit_char(read_char(&mut self.data)?)
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_string(visitor)
+        if !self.config.aligned_strings {
+            return self.deserialize_string(visitor);
+        }
+        expect_tag!(self, visitor, Str);
+        match self.read_len_and_aligned_bytes()? {
+            Cow::Borrowed(bytes) => visitor.visit_borrowed_str(
+                std::str::from_utf8(bytes).map_err(|_| E::Invalid("utf8").e())?,
+            ),
+            Cow::Owned(bytes) => {
+                visitor.visit_string(String::from_utf8(bytes).map_err(|_| E::Invalid("utf8").e())?)
+            }
+        }
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, Str);
         let bytes = self.read_len_and_bytes()?;
         visitor.visit_string(String::from_utf8(bytes).map_err(|_| E::Invalid("utf8").e())?)
     }
@@ -174,13 +773,21 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
-        self.deserialize_byte_buf(visitor)
+        if !self.config.aligned_strings {
+            return self.deserialize_byte_buf(visitor);
+        }
+        expect_tag!(self, visitor, Bytes);
+        match self.read_len_and_aligned_bytes()? {
+            Cow::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Cow::Owned(bytes) => visitor.visit_byte_buf(bytes),
+        }
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, Bytes);
         visitor.visit_byte_buf(self.read_len_and_bytes()?)
     }
 
@@ -188,8 +795,17 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
-        if self.read_bool()? {
-            visitor.visit_some(self)
+        let some = if self.config.self_describing {
+            match self.take_tag()? {
+                Tag::None => false,
+                Tag::Some => true,
+                tag => return self.visit_tag(tag, visitor),
+            }
+        } else {
+            self.read_bool()?
+        };
+        if some {
+            self.nested(0, |d| visitor.visit_some(d))
         } else {
             visitor.visit_none()
         }
@@ -199,6 +815,7 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, Unit);
         visitor.visit_unit()
     }
 
@@ -206,6 +823,7 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
+        expect_tag!(self, visitor, Unit);
         visitor.visit_unit()
     }
 
@@ -213,109 +831,91 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
-        visitor.visit_newtype_struct(self)
+        expect_tag!(self, visitor, NewtypeStruct);
+        self.nested(0, |d| visitor.visit_newtype_struct(d))
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let len = self.read_len()?;
-        self.deserialize_tuple(len, visitor)
+        expect_tag!(self, visitor, Seq);
+        self.nested(0, |d| {
+            let len = d.read_seq_len()?;
+            if d.config.columnar && !d.in_columns {
+                d.read_columns(|d| {
+                    visitor.visit_seq(SeqItems {
+                        deserializer: d,
+                        len,
+                        next_path: false,
+                    })
+                })
+            } else {
+                visitor.visit_seq(SeqItems {
+                    deserializer: d,
+                    len,
+                    next_path: false,
+                })
+            }
+        })
     }
 
-    // based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L293-L330
     fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        struct Access<'a, R> {
-            deserializer: &'a mut BitcodeDeserializer<R>,
-            len: usize,
-        }
-
-        impl<'de, R: Read> SeqAccess<'de> for Access<'_, R> {
-            type Error = Error;
-
-            fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
-            where
-                T: DeserializeSeed<'de>,
-            {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let value = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                    Ok(Some(value))
-                } else {
-                    Ok(None)
-                }
-            }
-
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
-            }
-        }
-
-        visitor.visit_seq(Access {
-            deserializer: self,
-            len,
-        })
+        expect_tag!(self, visitor, Tuple);
+        let len = self.read_tuple_len(len)?;
+        self.visit_items(len, 0, true, visitor)
     }
 
     fn deserialize_tuple_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         len: usize,
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        self.deserialize_tuple(len, visitor)
+        if let Some(max_bits) = bits_int_bits(name) {
+            // The len of a bits tuple struct is its number of bits, it always has 1 field.
+            let bits = if self.config.self_describing {
+                expect_tag!(self, visitor, Bits);
+                self.read_bits_len()?
+            } else if len > u64::BITS as usize {
+                return Err(E::NotSupported("bits > 64").e());
+            } else {
+                len
+            };
+            self.bits = Some((bits, max_bits));
+            return self.visit_items(1, 0, true, visitor);
+        }
+        expect_tag!(self, visitor, Tuple);
+        let len = self.read_tuple_len(len)?;
+        self.visit_items(len, 0, true, visitor)
     }
 
-    // based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L353-L400
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        struct Access<'a, R: Read> {
-            deserializer: &'a mut BitcodeDeserializer<R>,
-            len: usize,
-        }
-
-        impl<'de, R: Read> MapAccess<'de> for Access<'_, R> {
-            type Error = Error;
-
-            fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
-            where
-                K: DeserializeSeed<'de>,
-            {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let key = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                    Ok(Some(key))
-                } else {
-                    Ok(None)
-                }
-            }
-
-            fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
-            where
-                V: DeserializeSeed<'de>,
-            {
-                let value = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                Ok(value)
-            }
-
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
+        expect_tag!(self, visitor, Map);
+        self.nested(0, |d| {
+            let len = d.read_seq_len()?;
+            if d.config.columnar && !d.in_columns {
+                d.read_columns(|d| {
+                    visitor.visit_map(MapItems {
+                        deserializer: d,
+                        len,
+                    })
+                })
+            } else {
+                visitor.visit_map(MapItems {
+                    deserializer: d,
+                    len,
+                })
             }
-        }
-
-        let len = self.read_len()?;
-        visitor.visit_map(Access {
-            deserializer: self,
-            len,
         })
     }
 
@@ -328,48 +928,67 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     where
         V: Visitor<'de>,
     {
-        self.deserialize_tuple(fields.len(), visitor)
+        if self.config.self_describing {
+            expect_tag!(self, visitor, Struct);
+            return self.visit_named_fields(0, visitor);
+        }
+        if self.config.skippable_fields {
+            return self.visit_present_fields(0, visitor);
+        }
+        self.visit_items(fields.len(), 0, true, visitor)
     }
 
-    // based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L263-L291
     fn deserialize_enum<V>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        impl<'a, 'de, R: Read> EnumAccess<'de> for &'a mut BitcodeDeserializer<R> {
-            type Error = Error;
-            type Variant = &'a mut BitcodeDeserializer<R>;
-
-            fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
-            where
-                V: DeserializeSeed<'de>,
-            {
-                let idx = self.read_variant_index()?;
-                let val: Result<_> = seed.deserialize(idx.into_deserializer());
-                Ok((val?, self))
+        if self.config.self_describing {
+            let tag = self.take_tag()?;
+            if !matches!(
+                tag,
+                Tag::UnitVariant | Tag::NewtypeVariant | Tag::TupleVariant | Tag::StructVariant
+            ) {
+                return self.visit_tag(tag, visitor);
             }
+            let index = self.read_tagged_variant_index()?;
+            return visitor.visit_enum(Variant {
+                deserializer: self,
+                index,
+                tag: Some(tag),
+            });
         }
-
-        visitor.visit_enum(self)
+        let index = self.read_variant_index(name, variants.len())?;
+        visitor.visit_enum(Variant {
+            deserializer: self,
+            index,
+            tag: None,
+        })
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        return Err(E::NotSupported("deserialize_identifier").e());
+        if !self.config.self_describing {
+            return Err(E::NotSupported("deserialize_identifier").e());
+        }
+        self.deserialize_any(visitor)
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        return Err(E::NotSupported("deserialize_ignored_any").e());
+        if !self.config.self_describing {
+            return Err(E::NotSupported("deserialize_ignored_any").e());
+        }
+        self.deserialize_any(IgnoredAny)?;
+        visitor.visit_unit()
     }
 
     fn is_human_readable(&self) -> bool {
@@ -377,32 +996,315 @@ impl<'de, R: Read> Deserializer<'de> for &mut BitcodeDeserializer<R> {
     }
 }
 
+// based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L293-L330
+struct SeqItems<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    len: usize,
+    /// If each item has its own path (see [`Read::next_path`]).
+    next_path: bool,
+}
+
+impl<'de, R: Read<'de>> SeqAccess<'de> for SeqItems<'_, R> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.len > 0 {
+            self.len -= 1;
+            // Items with their own path are fields stored inline, elements of sequences aren't.
+            if !self.next_path {
+                self.deserializer.allocate_element::<T::Value>()?;
+            }
+            let value = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
+            if self.next_path {
+                self.deserializer.data.next_path();
+            }
+            Ok(Some(value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+// based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L353-L400
+struct MapItems<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    len: usize,
+}
+
+impl<'de, R: Read<'de>> MapAccess<'de> for MapItems<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.len > 0 {
+            self.len -= 1;
+            self.deserializer.allocate_element::<K::Value>()?;
+            // Keys and values have their own paths so their fields don't share columns.
+            self.deserializer.data.enter_path(0);
+            let key = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
+            self.deserializer.data.exit_path();
+            Ok(Some(key))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.deserializer
+            .allocate(std::mem::size_of::<V::Value>())?;
+        self.deserializer.data.enter_path(1);
+        let value = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
+        self.deserializer.data.exit_path();
+        Ok(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+// based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L263-L291
+impl<'de, R: Read<'de>> EnumAccess<'de> for Variant<'_, R> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let val: Result<_> = seed.deserialize(self.index.into_deserializer());
+        Ok((val?, self))
+    }
+}
+
+/// An enum variant whose index has been read. Its fields are nested at the slot of the variant.
+struct Variant<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    index: u32,
+    /// The tag of the variant with [`Config::with_self_describing_encoding`].
+    tag: Option<Tag>,
+}
+
+impl<'de, R: Read<'de>> Variant<'_, R> {
+    /// Checks that the variant was serialized as a `tag` variant.
+    fn expect_tag(&self, tag: Tag) -> Result<()> {
+        if self.tag.is_some_and(|t| t != tag) {
+            return Err(E::Invalid("tag").e());
+        }
+        Ok(())
+    }
+}
+
 // based on https://github.com/bincode-org/bincode/blob/c44b5e364e7084cdbabf9f94b63a3c7f32b8fb68/src/de/mod.rs#L461-L492
-impl<'de, R: Read> VariantAccess<'de> for &mut BitcodeDeserializer<R> {
+impl<'de, R: Read<'de>> VariantAccess<'de> for Variant<'_, R> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
-        Ok(())
+        self.expect_tag(Tag::UnitVariant)
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
     where
         T: DeserializeSeed<'de>,
     {
-        DeserializeSeed::deserialize(seed, self)
+        self.expect_tag(Tag::NewtypeVariant)?;
+        self.deserializer.nested(variant_slot(self.index), |d| {
+            DeserializeSeed::deserialize(seed, d)
+        })
     }
 
     fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Deserializer::deserialize_tuple(self, len, visitor)
+        self.expect_tag(Tag::TupleVariant)?;
+        let len = self.deserializer.read_tuple_len(len)?;
+        self.deserializer
+            .visit_items(len, variant_slot(self.index), true, visitor)
     }
 
     fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        Deserializer::deserialize_tuple(self, fields.len(), visitor)
+        let slot = variant_slot(self.index);
+        if self.tag.is_some() {
+            self.expect_tag(Tag::StructVariant)?;
+            return self.deserializer.visit_named_fields(slot, visitor);
+        }
+        if self.deserializer.config.skippable_fields {
+            return self.deserializer.visit_present_fields(slot, visitor);
+        }
+        self.deserializer
+            .visit_items(fields.len(), slot, true, visitor)
+    }
+}
+
+/// An enum variant read by `deserialize_any`, which is visited as a map with a single entry from
+/// its index to its fields.
+struct VariantMap<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    tag: Tag,
+    /// The index of the variant until it's visited.
+    index: Option<u32>,
+}
+
+impl<'de, R: Read<'de>> MapAccess<'de> for VariantMap<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        // Serde's buffered identifiers can't be u32s.
+        self.index
+            .map(|index| seed.deserialize((index as u64).into_deserializer()))
+            .transpose()
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let index = self.index.take().ok_or(E::Invalid("variant").e())?;
+        let slot = variant_slot(index);
+        match self.tag {
+            Tag::UnitVariant => seed.deserialize(().into_deserializer()),
+            Tag::NewtypeVariant => self
+                .deserializer
+                .nested(slot, |d| DeserializeSeed::deserialize(seed, d)),
+            tag => seed.deserialize(VariantFields {
+                deserializer: &mut *self.deserializer,
+                tag,
+                slot,
+            }),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.index.is_some() as usize)
+    }
+}
+
+/// The fields of a tuple or struct variant read by `deserialize_any`.
+struct VariantFields<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    tag: Tag,
+    slot: u64,
+}
+
+impl<'de, R: Read<'de>> Deserializer<'de> for VariantFields<'_, R> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if self.tag == Tag::StructVariant {
+            return self.deserializer.visit_named_fields(self.slot, visitor);
+        }
+        let len = self.deserializer.read_seq_len()?;
+        self.deserializer.visit_items(len, self.slot, true, visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+/// The fields of a struct written with [`Config::with_self_describing_encoding`].
+struct NamedFields<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    len: usize,
+}
+
+impl<'de, R: Read<'de>> MapAccess<'de> for NamedFields<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.len > 0 {
+            self.len -= 1;
+            let name = self.deserializer.read_len_and_bytes()?;
+            let name = String::from_utf8(name).map_err(|_| E::Invalid("utf8").e())?;
+            let key: Result<_> = seed.deserialize(name.into_deserializer());
+            Ok(Some(key?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
+        self.deserializer.data.next_path();
+        Ok(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// The present fields of a struct written with [`Config::with_skippable_fields`]. Each of them is
+/// preceded by a 1 bit and a 0 bit for every field skipped since the previous one. They're keyed
+/// by index rather than name since `fields` also contains aliases.
+struct PresentFields<'a, R> {
+    deserializer: &'a mut BitcodeDeserializer<R>,
+    /// The number of present fields that haven't been read yet.
+    len: usize,
+    /// The index of the next field.
+    index: u64,
+}
+
+impl<'de, R: Read<'de>> MapAccess<'de> for PresentFields<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        while !self.deserializer.data.read_bit()? {
+            self.index += 1;
+            self.deserializer.data.next_path();
+        }
+        let key: Result<_> = seed.deserialize(self.index.into_deserializer());
+        key.map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
+        self.index += 1;
+        self.deserializer.data.next_path();
+        Ok(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
     }
 }
@@ -1,26 +1,148 @@
 use crate::nightly::div_ceil;
-use crate::{Result, E};
+use crate::ser::write::Paths;
+use crate::{Config, Error, Result, E};
 use std::array;
-use std::marker::PhantomData;
+use std::borrow::Cow;
 
 type Word = u64;
 const WORD_BITS: usize = Word::BITS as usize;
 const WORD_BYTES: usize = std::mem::size_of::<Word>();
 
-pub trait Read {
-    fn finish(self) -> Result<()>;
+pub trait Read<'de> {
+    /// Checks that all the input was read and that the padding bits are zero.
+    fn finish(&mut self) -> Result<()>;
+    /// Returns how many bits have been read so far.
+    fn bits_read(&self) -> usize;
+    /// Skips the zero bits up to the next byte boundary.
+    fn align_to_byte(&mut self) -> Result<()>;
     /// Reads up to 64 bits. `bits` must be in range `1..=64`.
     fn read_bits(&mut self, bits: usize) -> Result<Word>;
     // Reads 1 bit.
     fn read_bit(&mut self) -> Result<bool>;
     /// Reads `len` bytes. `len` must be < `isize::MAX as usize / u8::BITS as usize`.
     fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>>;
+    /// Like [`Read::read_bytes`] but borrows the bytes from the input if possible. Must be called
+    /// after [`Read::align_to_byte`].
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'de, [u8]>>;
     /// Reads as many zeros as possible up to `max`. `max` must be in range `1..=63`.
     fn read_zeros(&mut self, max: usize) -> Result<usize>;
+
+    // Hooks for the columnar layout (see [`ColumnsRead`]), called like the ones of
+    // [`Write`][`crate::ser::write::Write`].
+
+    /// Enters a value nested in the current one at `slot`.
+    #[inline(always)]
+    fn enter_path(&mut self, _slot: u64) {}
+    /// Moves on to the next slot, e.g. the next field of a struct.
+    #[inline(always)]
+    fn next_path(&mut self) {}
+    /// Exits the value entered by [`Read::enter_path`].
+    #[inline(always)]
+    fn exit_path(&mut self) {}
+
+    // Hooks for the limits of [`Decode`][`crate::Decode`] (see [`DecodeRead`]), which has no
+    // deserializer to keep track of them.
+
+    /// Enters a nested value, returning an error if [`Config::with_max_depth`] is exceeded.
+    #[inline(always)]
+    fn enter(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Exits the value entered by [`Read::enter`].
+    #[inline(always)]
+    fn exit(&mut self) {}
+    /// Counts `len` bytes towards [`Config::with_allocation_limit`]. Must be called before
+    /// allocating.
+    #[inline(always)]
+    fn allocate(&mut self, _len: usize) -> Result<()> {
+        Ok(())
+    }
 }
 
-pub trait ReadWith<'a>: Read {
-    fn from_inner(inner: &'a [u8]) -> Self;
+impl<'de, R: Read<'de>> Read<'de> for &mut R {
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+
+    fn bits_read(&self) -> usize {
+        (**self).bits_read()
+    }
+
+    fn align_to_byte(&mut self) -> Result<()> {
+        (**self).align_to_byte()
+    }
+
+    #[inline(always)]
+    fn read_bits(&mut self, bits: usize) -> Result<Word> {
+        (**self).read_bits(bits)
+    }
+
+    #[inline(always)]
+    fn read_bit(&mut self) -> Result<bool> {
+        (**self).read_bit()
+    }
+
+    #[inline(always)]
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        (**self).read_bytes(len)
+    }
+
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'de, [u8]>> {
+        (**self).read_aligned_bytes(len)
+    }
+
+    #[inline(always)]
+    fn read_zeros(&mut self, max: usize) -> Result<usize> {
+        (**self).read_zeros(max)
+    }
+
+    #[inline(always)]
+    fn enter_path(&mut self, slot: u64) {
+        (**self).enter_path(slot)
+    }
+
+    #[inline(always)]
+    fn next_path(&mut self) {
+        (**self).next_path()
+    }
+
+    #[inline(always)]
+    fn exit_path(&mut self) {
+        (**self).exit_path()
+    }
+
+    #[inline(always)]
+    fn enter(&mut self) -> Result<()> {
+        (**self).enter()
+    }
+
+    #[inline(always)]
+    fn exit(&mut self) {
+        (**self).exit()
+    }
+
+    #[inline(always)]
+    fn allocate(&mut self, len: usize) -> Result<()> {
+        (**self).allocate(len)
+    }
+}
+
+pub trait ReadWith<'a>: Read<'a> {
+    /// An allocation that can be reused between inputs.
+    type Buffer: Default;
+
+    fn from_inner(inner: &'a [u8]) -> Self
+    where
+        Self: Sized,
+    {
+        Self::from_inner_in(inner, Default::default())
+    }
+
+    /// Like [`ReadWith::from_inner`] but reuses the allocation of a previous reader.
+    fn from_inner_in(inner: &'a [u8], buffer: Self::Buffer) -> Self;
+
+    /// Returns the allocation so it can be passed to [`ReadWith::from_inner_in`].
+    fn into_buffer(self) -> Self::Buffer;
 }
 
 #[cfg(target_endian = "little")]
@@ -38,15 +160,25 @@ mod bitvec_read {
     use bitvec::domain::Domain;
     use bitvec::prelude::*;
 
-    pub type BitSliceImpl<'a> = &'a BitSlice<u8, Lsb0>;
+    pub struct BitSliceImpl<'a> {
+        slice: &'a BitSlice<u8, Lsb0>,
+        read: usize,
+    }
 
-    impl<'a> Read for BitSliceImpl<'a> {
-        fn finish(self) -> Result<()> {
-            if self.is_empty() {
+    impl<'a> BitSliceImpl<'a> {
+        fn advance(&mut self, bits: usize) {
+            self.slice = &self.slice[bits..];
+            self.read += bits;
+        }
+    }
+
+    impl<'a> Read<'a> for BitSliceImpl<'a> {
+        fn finish(&mut self) -> Result<()> {
+            if self.slice.is_empty() {
                 return Ok(());
             }
 
-            let e = match self.domain() {
+            let e = match self.slice.domain() {
                 Domain::Enclave(e) => e,
                 Domain::Region { head, body, tail } => {
                     if !body.is_empty() {
@@ -60,9 +192,22 @@ mod bitvec_read {
                 .ok_or(E::ExpectedEof.e())
         }
 
+        fn bits_read(&self) -> usize {
+            self.read
+        }
+
+        fn align_to_byte(&mut self) -> Result<()> {
+            let padding = (u8::BITS as usize - self.slice.as_bitptr().bit().into_inner() as usize)
+                % u8::BITS as usize;
+            if padding != 0 && self.read_bits(padding)? != 0 {
+                return Err(E::Invalid("padding").e());
+            }
+            Ok(())
+        }
+
         fn read_bits(&mut self, bits: usize) -> Result<Word> {
-            let slice = self.get(..bits).ok_or(E::Eof.e())?;
-            *self = &self[bits..];
+            let slice = self.slice.get(..bits).ok_or(E::Eof.e())?;
+            self.advance(bits);
 
             let mut v = [0; 8];
             BitSlice::<u8, Lsb0>::from_slice_mut(&mut v)[..bits].copy_from_bitslice(slice);
@@ -70,28 +215,44 @@ mod bitvec_read {
         }
 
         fn read_bit(&mut self) -> Result<bool> {
-            let v = *self.get(0).ok_or(E::Eof.e())?;
-            *self = &self[1..];
+            let v = *self.slice.get(0).ok_or(E::Eof.e())?;
+            self.advance(1);
             Ok(v)
         }
 
         fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
             let bits = len * u8::BITS as usize;
-            let slice = self.get(..bits).ok_or(E::Eof.e())?;
-            *self = &self[bits..];
+            let slice = self.slice.get(..bits).ok_or(E::Eof.e())?;
+            self.advance(bits);
 
             let mut vec = vec![0u8; len];
             vec.as_mut_bits().copy_from_bitslice(slice);
             Ok(vec)
         }
 
+        fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'a, [u8]>> {
+            let bits = len * u8::BITS as usize;
+            let slice: &'a BitSlice<u8, Lsb0> = self.slice.get(..bits).ok_or(E::Eof.e())?;
+            match slice.domain() {
+                Domain::Region {
+                    head: None,
+                    body,
+                    tail: None,
+                } => {
+                    self.advance(bits);
+                    Ok(Cow::Borrowed(body))
+                }
+                _ => self.read_bytes(len).map(Cow::Owned),
+            }
+        }
+
         fn read_zeros(&mut self, max: usize) -> Result<usize> {
-            let zeros = self.leading_zeros();
+            let zeros = self.slice.leading_zeros();
             if zeros > max {
                 Err(E::Invalid("zeros").e())
             } else {
-                *self = &self[zeros..];
-                let next = *self.get(0).ok_or(E::Eof.e())?;
+                self.advance(zeros);
+                let next = *self.slice.get(0).ok_or(E::Eof.e())?;
                 debug_assert!(next);
                 Ok(zeros)
             }
@@ -99,18 +260,24 @@ mod bitvec_read {
     }
 
     impl<'a> ReadWith<'a> for BitSliceImpl<'a> {
-        fn from_inner(inner: &'a [u8]) -> Self {
-            BitSlice::from_slice(inner)
+        type Buffer = ();
+
+        fn from_inner_in(inner: &'a [u8], _: ()) -> Self {
+            Self {
+                slice: BitSlice::from_slice(inner),
+                read: 0,
+            }
         }
+
+        fn into_buffer(self) {}
     }
 }
 
 #[derive(Debug)]
 pub struct DeVec<'a> {
-    words: Box<[Word]>,
+    words: Vec<Word>,
     read: usize,
-    bytes: usize,
-    _spooky: PhantomData<&'a ()>, // To be compatible with BitVec.
+    inner: &'a [u8],
 }
 
 impl<'a> DeVec<'a> {
@@ -167,8 +334,8 @@ impl<'a> DeVec<'a> {
     }
 }
 
-impl<'a> Read for DeVec<'a> {
-    fn finish(self) -> Result<()> {
+impl<'a> Read<'a> for DeVec<'a> {
+    fn finish(&mut self) -> Result<()> {
         let bytes_read = div_ceil(self.read, u8::BITS as usize);
         let index = self.read / WORD_BITS;
         let bits_written = self.read % WORD_BITS;
@@ -177,9 +344,9 @@ impl<'a> Read for DeVec<'a> {
             return Err(E::ExpectedEof.e());
         }
 
-        if bytes_read < self.bytes {
+        if bytes_read < self.inner.len() {
             Err(E::ExpectedEof.e())
-        } else if bytes_read > self.bytes {
+        } else if bytes_read > self.inner.len() {
             // It is possible that we read more bytes than we have (bytes are rounded up to words).
             // We don't check this while deserializing to avoid degrading performance.
             Err(E::Eof.e())
@@ -188,6 +355,18 @@ impl<'a> Read for DeVec<'a> {
         }
     }
 
+    fn bits_read(&self) -> usize {
+        self.read
+    }
+
+    fn align_to_byte(&mut self) -> Result<()> {
+        let padding = (u8::BITS as usize - self.read % u8::BITS as usize) % u8::BITS as usize;
+        if padding != 0 && self.read_bits(padding)? != 0 {
+            return Err(E::Invalid("padding").e());
+        }
+        Ok(())
+    }
+
     fn read_bits(&mut self, bits: usize) -> Result<Word> {
         self.reserve_1_to_64(bits)?;
         Ok(self.read_reserved_bits(bits))
@@ -222,6 +401,17 @@ impl<'a> Read for DeVec<'a> {
         Ok(vec)
     }
 
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'a, [u8]>> {
+        debug_assert_eq!(self.read % u8::BITS as usize, 0);
+        let start = self.read / u8::BITS as usize;
+        let bytes = start
+            .checked_add(len)
+            .and_then(|end| self.inner.get(start..end))
+            .ok_or(E::Eof.e())?;
+        self.read += len * u8::BITS as usize;
+        Ok(Cow::Borrowed(bytes))
+    }
+
     fn read_zeros(&mut self, max: usize) -> Result<usize> {
         let max_plus_one = max + 1;
         self.reserve_1_to_64(max_plus_one)?;
@@ -237,10 +427,13 @@ impl<'a> Read for DeVec<'a> {
 }
 
 impl<'a> ReadWith<'a> for DeVec<'a> {
-    fn from_inner(inner: &'a [u8]) -> Self {
+    type Buffer = Vec<Word>;
+
+    fn from_inner_in(inner: &'a [u8], mut vec: Vec<Word>) -> Self {
         // u8s rounded up to u64s plus 1 u64 padding.
         let capacity = div_ceil(inner.len(), WORD_BYTES) + Self::PADDING;
-        let mut vec = Vec::with_capacity(capacity);
+        vec.clear();
+        vec.reserve_exact(capacity);
 
         // Fast hot loop (would be nicer with array_chunks, but that requires nightly).
         let chunks = inner.chunks_exact(WORD_BYTES);
@@ -262,11 +455,540 @@ impl<'a> ReadWith<'a> for DeVec<'a> {
         debug_assert_eq!(vec.len(), capacity);
 
         Self {
-            words: vec.into_boxed_slice(),
+            words: vec,
+            read: 0,
+            inner,
+        }
+    }
+
+    fn into_buffer(self) -> Vec<Word> {
+        self.words
+    }
+}
+
+/// A [`Read`] that reads from an [`std::io::Read`] as it goes instead of requiring the whole
+/// input up front. Never borrows from the input.
+pub struct IoRead<R> {
+    inner: R,
+    /// Bytes read from `inner` that haven't been moved to `bits` yet.
+    buffer: Box<[u8]>,
+    start: usize,
+    end: usize,
+    /// Bits that have been taken from `buffer` but not read yet, least significant first.
+    bits: u128,
+    len: usize,
+    /// Bits read so far.
+    read: usize,
+    /// Bytes taken from `buffer` so far.
+    bytes: usize,
+    /// Minimum length of the input in bits, known after returning [`E::Eof`].
+    bits_needed: usize,
+    limit: Option<usize>,
+}
+
+impl<R: std::io::Read> IoRead<R> {
+    /// How many bytes are read from `inner` at a time.
+    const BUFFER_BYTES: usize = 4096;
+
+    /// Creates an [`IoRead`] that returns an error once more than `limit` bytes are read.
+    pub fn new(inner: R, limit: Option<usize>) -> Self {
+        Self {
+            inner,
+            buffer: vec![0; Self::BUFFER_BYTES].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            bits: 0,
+            len: 0,
+            read: 0,
+            bytes: 0,
+            bits_needed: 0,
+            limit,
+        }
+    }
+
+    /// Returns the next byte of `inner` or [`None`] if it has ended.
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        if self.start == self.end {
+            self.start = 0;
+            self.end = loop {
+                match self.inner.read(&mut self.buffer) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(E::Io(e.kind()).e()),
+                }
+            };
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+
+        self.bytes += 1;
+        if self.limit.is_some_and(|limit| self.bytes > limit) {
+            return Err(E::LimitExceeded("size").e());
+        }
+        let byte = self.buffer[self.start];
+        self.start += 1;
+        Ok(Some(byte))
+    }
+
+    /// Takes bytes from `inner` until at least `bits` bits are available or `inner` has ended.
+    /// `bits` must be in range `1..=64`. Returns the number of bits available.
+    fn fill(&mut self, bits: usize) -> Result<usize> {
+        debug_assert!((1..=WORD_BITS).contains(&bits));
+        while self.len < bits {
+            let Some(byte) = self.next_byte()? else {
+                break;
+            };
+            self.bits |= (byte as u128) << self.len;
+            self.len += u8::BITS as usize;
+        }
+        Ok(self.len)
+    }
+
+    /// Returns the minimum length of the input in bits, which is more than its actual length if
+    /// [`E::Eof`] was returned.
+    pub fn bits_needed(&self) -> usize {
+        self.bits_needed.max(self.read)
+    }
+
+    fn eof(&mut self, bits: usize) -> Error {
+        self.bits_needed = self.read + bits;
+        E::Eof.e()
+    }
+
+    fn consume(&mut self, bits: usize) -> Word {
+        let v = (self.bits & ((1 << bits) - 1)) as Word;
+        self.bits >>= bits;
+        self.len -= bits;
+        self.read += bits;
+        v
+    }
+}
+
+impl<'de, R: std::io::Read> Read<'de> for IoRead<R> {
+    fn finish(&mut self) -> Result<()> {
+        // Bits left over from the last byte must be zero and there must be no more bytes.
+        if self.len >= u8::BITS as usize || self.bits != 0 || self.next_byte()?.is_some() {
+            Err(E::ExpectedEof.e())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn bits_read(&self) -> usize {
+        self.read
+    }
+
+    fn align_to_byte(&mut self) -> Result<()> {
+        let padding = (u8::BITS as usize - self.read % u8::BITS as usize) % u8::BITS as usize;
+        if padding != 0 && self.read_bits(padding)? != 0 {
+            return Err(E::Invalid("padding").e());
+        }
+        Ok(())
+    }
+
+    fn read_bits(&mut self, bits: usize) -> Result<Word> {
+        if self.fill(bits)? < bits {
+            return Err(self.eof(bits));
+        }
+        Ok(self.consume(bits))
+    }
+
+    fn read_bit(&mut self) -> Result<bool> {
+        self.read_bits(1).map(|v| v != 0)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        // If the input ends early, all of the bytes are needed and not just the next word.
+        let bits_needed = self.read + len * u8::BITS as usize;
+        let mut read_bytes = || {
+            // The input might end before len bytes so don't trust len with the allocation.
+            let mut vec = Vec::with_capacity(len.min(Self::BUFFER_BYTES));
+            for _ in 0..len / WORD_BYTES {
+                vec.extend_from_slice(&self.read_bits(WORD_BITS)?.to_le_bytes());
+            }
+            for _ in 0..len % WORD_BYTES {
+                vec.push(self.read_bits(u8::BITS as usize)? as u8);
+            }
+            Ok(vec)
+        };
+        let result: Result<Vec<u8>> = read_bytes();
+        if result.as_ref().is_err_and(|e| e.same(&E::Eof.e())) {
+            self.bits_needed = self.bits_needed.max(bits_needed);
+        }
+        result
+    }
+
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'de, [u8]>> {
+        self.read_bytes(len).map(Cow::Owned)
+    }
+
+    fn read_zeros(&mut self, max: usize) -> Result<usize> {
+        let max_plus_one = max + 1;
+        let available = self.fill(max_plus_one)?.min(max_plus_one);
+        let zeros = (self.bits.trailing_zeros() as usize).min(available);
+        if zeros == max_plus_one {
+            Err(E::Invalid("zeros").e())
+        } else if zeros == available {
+            Err(self.eof(zeros + 1))
+        } else {
+            self.consume(zeros);
+            Ok(zeros)
+        }
+    }
+}
+
+/// A [`Read`] that returns an error instead of reading more than `limit` bytes past the bits that
+/// were already read from `inner`.
+pub struct LimitRead<R> {
+    inner: R,
+    /// Bits of `inner` read before this value plus `limit` bytes.
+    end: usize,
+}
+
+impl<'de, R: Read<'de>> LimitRead<R> {
+    /// Creates a [`LimitRead`] that reads from `inner`, which has no limit if `limit` is [`None`].
+    pub fn new(inner: R, limit: Option<usize>) -> Self {
+        let end = limit.map_or(usize::MAX, |limit| {
+            inner
+                .bits_read()
+                .saturating_add(limit.saturating_mul(u8::BITS as usize))
+        });
+        Self { inner, end }
+    }
+
+    /// Returns an error if reading `bits` more bits would exceed the limit.
+    #[inline(always)]
+    fn reserve(&self, bits: usize) -> Result<()> {
+        if bits > self.end - self.inner.bits_read().min(self.end) {
+            return Err(E::LimitExceeded("size").e());
+        }
+        Ok(())
+    }
+}
+
+impl<'de, R: Read<'de>> Read<'de> for LimitRead<R> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+
+    fn bits_read(&self) -> usize {
+        self.inner.bits_read()
+    }
+
+    fn align_to_byte(&mut self) -> Result<()> {
+        self.inner.align_to_byte()
+    }
+
+    #[inline(always)]
+    fn read_bits(&mut self, bits: usize) -> Result<Word> {
+        self.reserve(bits)?;
+        self.inner.read_bits(bits)
+    }
+
+    #[inline(always)]
+    fn read_bit(&mut self) -> Result<bool> {
+        self.reserve(1)?;
+        self.inner.read_bit()
+    }
+
+    #[inline(always)]
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        self.reserve(len.saturating_mul(u8::BITS as usize))?;
+        self.inner.read_bytes(len)
+    }
+
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'de, [u8]>> {
+        self.reserve(len.saturating_mul(u8::BITS as usize))?;
+        self.inner.read_aligned_bytes(len)
+    }
+
+    #[inline(always)]
+    fn read_zeros(&mut self, max: usize) -> Result<usize> {
+        let zeros = self.inner.read_zeros(max)?;
+        // The 1 after the zeros is part of the value too.
+        self.reserve(1)?;
+        Ok(zeros)
+    }
+
+    #[inline(always)]
+    fn enter_path(&mut self, slot: u64) {
+        self.inner.enter_path(slot)
+    }
+
+    #[inline(always)]
+    fn next_path(&mut self) {
+        self.inner.next_path()
+    }
+
+    #[inline(always)]
+    fn exit_path(&mut self) {
+        self.inner.exit_path()
+    }
+
+    #[inline(always)]
+    fn enter(&mut self) -> Result<()> {
+        self.inner.enter()
+    }
+
+    #[inline(always)]
+    fn exit(&mut self) {
+        self.inner.exit()
+    }
+
+    #[inline(always)]
+    fn allocate(&mut self, len: usize) -> Result<()> {
+        self.inner.allocate(len)
+    }
+}
+
+/// A [`Read`] that keeps track of [`Config::with_max_depth`] and
+/// [`Config::with_allocation_limit`] for [`Decode`][`crate::Decode`].
+pub struct DecodeRead<R> {
+    inner: R,
+    /// Remaining bytes before [`Config::with_allocation_limit`] is exceeded.
+    allocation_budget: usize,
+    /// Remaining levels of nesting before [`Config::with_max_depth`] is exceeded.
+    depth_budget: usize,
+}
+
+impl<'de, R: Read<'de>> DecodeRead<R> {
+    /// Creates a [`DecodeRead`] that reads from `inner` with the limits of `config`.
+    pub fn new(inner: R, config: &Config) -> Self {
+        Self {
+            inner,
+            allocation_budget: config.allocation_limit.unwrap_or(usize::MAX),
+            depth_budget: config.max_depth.unwrap_or(usize::MAX),
+        }
+    }
+}
+
+impl<'de, R: Read<'de>> Read<'de> for DecodeRead<R> {
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+
+    fn bits_read(&self) -> usize {
+        self.inner.bits_read()
+    }
+
+    fn align_to_byte(&mut self) -> Result<()> {
+        self.inner.align_to_byte()
+    }
+
+    #[inline(always)]
+    fn read_bits(&mut self, bits: usize) -> Result<Word> {
+        self.inner.read_bits(bits)
+    }
+
+    #[inline(always)]
+    fn read_bit(&mut self) -> Result<bool> {
+        self.inner.read_bit()
+    }
+
+    #[inline(always)]
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        self.inner.read_bytes(len)
+    }
+
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'de, [u8]>> {
+        self.inner.read_aligned_bytes(len)
+    }
+
+    #[inline(always)]
+    fn read_zeros(&mut self, max: usize) -> Result<usize> {
+        self.inner.read_zeros(max)
+    }
+
+    #[inline(always)]
+    fn enter(&mut self) -> Result<()> {
+        self.depth_budget = self
+            .depth_budget
+            .checked_sub(1)
+            .ok_or(E::LimitExceeded("depth").e())?;
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn exit(&mut self) {
+        self.depth_budget += 1;
+    }
+
+    #[inline(always)]
+    fn allocate(&mut self, len: usize) -> Result<()> {
+        self.allocation_budget = self
+            .allocation_budget
+            .checked_sub(len)
+            .ok_or(E::LimitExceeded("allocation").e())?;
+        Ok(())
+    }
+}
+
+/// A column written by [`ColumnsWrite`][`crate::ser::write::ColumnsWrite`].
+pub struct Column {
+    words: Vec<Word>,
+    /// Length in bits.
+    len: usize,
+    read: usize,
+    /// The position in bits of the column in the input.
+    start: usize,
+}
+
+impl Column {
+    /// Reads a column of `len` bits from `r`.
+    pub fn read_from<'de>(r: &mut impl Read<'de>, len: usize) -> Result<Self> {
+        let start = r.bits_read();
+        // The input might end before len bits so don't trust len with the allocation.
+        let mut words = vec![];
+        for _ in 0..len / WORD_BITS {
+            words.push(r.read_bits(WORD_BITS)?);
+        }
+        let remainder = len % WORD_BITS;
+        if remainder != 0 {
+            words.push(r.read_bits(remainder)?);
+        }
+        Ok(Self {
+            words,
+            len,
             read: 0,
-            bytes: inner.len(),
-            _spooky: PhantomData,
+            start,
+        })
+    }
+
+    fn read_bits(&mut self, bits: usize) -> Result<Word> {
+        debug_assert!((1..=WORD_BITS).contains(&bits));
+        if bits > self.len - self.read {
+            return Err(E::Eof.e());
         }
+
+        let index = self.read / WORD_BITS;
+        let bit_remainder = self.read % WORD_BITS;
+        let a = self.words[index] >> bit_remainder;
+        let b = self.words.get(index + 1).map_or(0, |&w| {
+            w.checked_shl((WORD_BITS - bit_remainder) as u32)
+                .unwrap_or(0)
+        });
+        self.read += bits;
+
+        // Bits after len are zero so only bits read past the current word must be cleared.
+        let extra_bits = WORD_BITS - bits;
+        Ok(((a | b) << extra_bits) >> extra_bits)
+    }
+}
+
+/// A [`Read`] that reads each path (see [`Read::enter_path`]) from its own [`Column`]. Columns
+/// are numbered in the order their paths are first entered, like in
+/// [`ColumnsWrite`][`crate::ser::write::ColumnsWrite`].
+pub struct ColumnsRead {
+    columns: Vec<Column>,
+    paths: Paths,
+    current: usize,
+}
+
+impl ColumnsRead {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Self {
+            columns,
+            paths: Default::default(),
+            current: 0,
+        }
+    }
+
+    /// Returns the position in the input of the next bit of the current column, or the end of the
+    /// last column if fewer columns were written.
+    pub fn position(&self) -> usize {
+        match self.columns.get(self.current) {
+            Some(c) => c.start + c.read,
+            None => self.columns.last().map_or(0, |c| c.start + c.len),
+        }
+    }
+
+    /// Returns the current column or [`E::Eof`] if fewer columns were written.
+    fn column(&mut self) -> Result<&mut Column> {
+        self.columns.get_mut(self.current).ok_or(E::Eof.e())
+    }
+}
+
+impl<'de> Read<'de> for ColumnsRead {
+    fn finish(&mut self) -> Result<()> {
+        // Every column must have been read to the end.
+        if self.columns.iter().all(|c| c.read == c.len) {
+            Ok(())
+        } else {
+            Err(E::ExpectedEof.e())
+        }
+    }
+
+    fn bits_read(&self) -> usize {
+        self.columns.iter().map(|c| c.read).sum()
+    }
+
+    fn align_to_byte(&mut self) -> Result<()> {
+        let column = self.column()?;
+        let padding = (u8::BITS as usize - column.read % u8::BITS as usize) % u8::BITS as usize;
+        if padding != 0 && column.read_bits(padding)? != 0 {
+            return Err(E::Invalid("padding").e());
+        }
+        Ok(())
+    }
+
+    fn read_bits(&mut self, bits: usize) -> Result<Word> {
+        self.column()?.read_bits(bits)
+    }
+
+    fn read_bit(&mut self) -> Result<bool> {
+        self.read_bits(1).map(|v| v != 0)
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        let column = self.column()?;
+        if len > (column.len - column.read) / u8::BITS as usize {
+            return Err(E::Eof.e());
+        }
+        let mut vec = Vec::with_capacity(len);
+        for _ in 0..len / WORD_BYTES {
+            vec.extend_from_slice(&column.read_bits(WORD_BITS)?.to_le_bytes());
+        }
+        for _ in 0..len % WORD_BYTES {
+            vec.push(column.read_bits(u8::BITS as usize)? as u8);
+        }
+        Ok(vec)
+    }
+
+    fn read_aligned_bytes(&mut self, len: usize) -> Result<Cow<'de, [u8]>> {
+        self.read_bytes(len).map(Cow::Owned)
+    }
+
+    fn read_zeros(&mut self, max: usize) -> Result<usize> {
+        let column = self.column()?;
+        let max_plus_one = max + 1;
+        let available = max_plus_one.min(column.len - column.read);
+        if available == 0 {
+            return Err(E::Eof.e());
+        }
+
+        let zeros = (column.read_bits(available)?.trailing_zeros() as usize).min(available);
+        if zeros == max_plus_one {
+            Err(E::Invalid("zeros").e())
+        } else if zeros == available {
+            Err(E::Eof.e())
+        } else {
+            // Only the zeros and not the bit after them are read.
+            column.read -= available - zeros;
+            Ok(zeros)
+        }
+    }
+
+    fn enter_path(&mut self, slot: u64) {
+        self.current = self.paths.enter(slot);
+    }
+
+    fn next_path(&mut self) {
+        self.current = self.paths.next();
+    }
+
+    fn exit_path(&mut self) {
+        self.current = self.paths.exit();
     }
 }
 
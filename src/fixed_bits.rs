@@ -0,0 +1,131 @@
+use serde::de::{Error as _, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTupleStruct;
+use serde::{Deserializer, Serializer};
+use std::fmt::{self, Formatter};
+use std::marker::PhantomData;
+
+/// Serde `with` adapter that (de)serializes an unsigned integer with exactly `N` bits instead of
+/// all of its bits. `N` must be in range `0..=64`.
+///
+/// Serializing a value that doesn't fit in `N` bits returns an [`ErrorKind::Invalid`] error, as
+/// does deserializing a value that doesn't fit in the field's type.
+///
+/// ```edition2021
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct Tile {
+///     #[serde(with = "bitcode::bits::<9>")]
+///     x: u16,
+///     #[serde(with = "bitcode::bits::<9>")]
+///     y: u16,
+///     #[serde(with = "bitcode::bits::<5>")]
+///     height: u8,
+/// }
+///
+/// let tile = Tile { x: 511, y: 20, height: 19 };
+/// assert_eq!(bitcode::serialized_size_bits(&tile).unwrap(), 23);
+///
+/// let encoded = bitcode::serialize(&tile).unwrap();
+/// assert_eq!(bitcode::deserialize::<Tile>(&encoded).unwrap(), tile);
+///
+/// let too_far = Tile { x: 512, y: 0, height: 0 };
+/// let error = bitcode::serialize(&too_far).unwrap_err();
+/// assert_eq!(error.kind(), bitcode::ErrorKind::Invalid);
+/// ```
+///
+/// Other serde formats see a tuple struct containing a [`u64`].
+///
+/// [`ErrorKind::Invalid`]: crate::ErrorKind::Invalid
+#[allow(non_camel_case_types)]
+pub struct bits<const N: usize>;
+
+impl<const N: usize> bits<N> {
+    /// Serializes `v` with `N` bits.
+    pub fn serialize<T: BitsInt, S: Serializer>(v: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        // The serializer recognizes the name and writes the field with len bits.
+        let mut s = serializer.serialize_tuple_struct(T::NAME, N)?;
+        s.serialize_field(&v.to_u64())?;
+        s.end()
+    }
+
+    /// Deserializes a `T` serialized by [`bits::serialize`].
+    pub fn deserialize<'de, T: BitsInt, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        deserializer.deserialize_tuple_struct(T::NAME, N, BitsVisitor::<T, N>(PhantomData))
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// An unsigned integer that can be (de)serialized with [`bits`].
+pub trait BitsInt: Copy + private::Sealed {
+    #[doc(hidden)]
+    const NAME: &'static str;
+    #[doc(hidden)]
+    fn to_u64(self) -> u64;
+    #[doc(hidden)]
+    fn from_u64(v: u64) -> Option<Self>;
+}
+
+macro_rules! impl_bits_int {
+    ($($t:ty),*) => {
+        $(
+            impl private::Sealed for $t {}
+
+            impl BitsInt for $t {
+                const NAME: &'static str = concat!("bitcode::bits::<", stringify!($t), ">");
+
+                fn to_u64(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_u64(v: u64) -> Option<Self> {
+                    v.try_into().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_bits_int!(u8, u16, u32, u64, usize);
+
+/// Returns the number of bits of the [`BitsInt`] called `name` or [`None`] if `name` isn't the
+/// name of one.
+pub(crate) fn bits_int_bits(name: &str) -> Option<usize> {
+    Some(match name {
+        <u8 as BitsInt>::NAME => u8::BITS,
+        <u16 as BitsInt>::NAME => u16::BITS,
+        <u32 as BitsInt>::NAME => u32::BITS,
+        <u64 as BitsInt>::NAME => u64::BITS,
+        <usize as BitsInt>::NAME => usize::BITS,
+        _ => return None,
+    } as usize)
+}
+
+/// Returns if `v` fits in `n` bits.
+pub(crate) fn fits(v: u64, n: usize) -> bool {
+    v.checked_shr(n as u32).unwrap_or(0) == 0
+}
+
+struct BitsVisitor<T, const N: usize>(PhantomData<T>);
+
+impl<'de, T: BitsInt, const N: usize> Visitor<'de> for BitsVisitor<T, N> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "an integer with at most {N} bits")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let v: u64 = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        T::from_u64(v)
+            .filter(|_| fits(v, N))
+            .ok_or_else(|| A::Error::invalid_value(Unexpected::Unsigned(v), &self))
+    }
+}
@@ -8,6 +8,8 @@
 //!
 //! The format is not necessarily stable between versions. If you want
 //! a stable format, consider [bincode](https://docs.rs/bincode/latest/bincode/).
+//! [`serialize_versioned`] records the format version, so stored data that an
+//! upgrade can no longer read is rejected instead of deserialized as garbage.
 //!
 //! ### Usage
 //!
@@ -26,16 +28,54 @@ extern crate core;
 #[cfg(test)]
 extern crate test;
 
-use de::{deserialize_with, read::ReadWithImpl};
-use ser::{serialize_with, write::WriteWithImpl};
+// Lets #[derive(Encode, Decode)] refer to ::bitcode in tests.
+#[cfg(test)]
+extern crate self as bitcode;
+
+#[cfg(feature = "derive")]
+pub use bitcode_derive::{Decode, Encode};
+pub use buffer::{Decoder, Encoder, Progress, PushDecoder};
+#[doc(hidden)]
+pub use code::__private;
+use code::decode_with;
+pub use code::{Decode, Encode};
+#[cfg(feature = "tokio")]
+pub use codec::BitcodeCodec;
+pub use config::Config;
+use de::read::ReadWithImpl;
+use de::{
+    deserialize_from_reader_with, deserialize_prefix_with, deserialize_versioned_with,
+    deserialize_with,
+};
+pub use fixed_bits::{bits, BitsInt};
+pub use pack::{Packer, Unpacker};
+pub use quantized::{
+    Quantized, QuantizedAngle, QuantizedFloat, QuantizedUnitVector, RoundDown, RoundNearest,
+    RoundUp, Rounding,
+};
+use ser::write::{WriteWith, WriteWithImpl};
+use ser::{
+    serialize_into_slice_with, serialize_into_writer_with, serialize_versioned_with,
+    serialize_with, serialized_size_bits_with,
+};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Display, Formatter};
 
 #[cfg(all(test, not(miri)))]
 mod benches;
+mod buffer;
+mod code;
+#[cfg(feature = "tokio")]
+mod codec;
+mod config;
 mod de;
+mod fixed_bits;
 mod nightly;
+mod pack;
+mod quantized;
 mod ser;
+mod tag;
 #[cfg(test)]
 mod tests;
 
@@ -46,7 +86,67 @@ pub fn serialize<T: ?Sized>(t: &T) -> Result<Vec<u8>>
 where
     T: Serialize,
 {
-    serialize_with::<WriteWithImpl>(t)
+    serialize_with::<WriteWithImpl>(t, &Config::default())
+}
+
+/// Serializes a `T:` [`Serialize`] into an [`std::io::Write`]. Unlike [`serialize`], only a
+/// small buffer is kept in memory, so large values can be written to files and sockets.
+///
+/// The output is the same as [`serialize`]'s. If an error is returned, part of the output might
+/// have been written already.
+///
+/// **Warning:** The format is subject to change between versions.
+pub fn serialize_into_writer<T>(w: impl std::io::Write, t: &T) -> Result<()>
+where
+    T: Serialize + ?Sized,
+{
+    serialize_into_writer_with(t, w, &Config::default())
+}
+
+/// Returns the size of `T:` [`Serialize`] in bits without serializing it. [`serialize`] pads it
+/// to a whole byte.
+///
+/// ```edition2021
+/// let bits = bitcode::serialized_size_bits(&(true, 5u8)).unwrap();
+/// assert_eq!(bits, 9);
+/// assert_eq!(bitcode::serialize(&(true, 5u8)).unwrap().len(), 2);
+/// ```
+pub fn serialized_size_bits<T>(t: &T) -> Result<usize>
+where
+    T: Serialize + ?Sized,
+{
+    serialized_size_bits_with(t, &Config::default())
+}
+
+/// Serializes a `T:` [`Serialize`] into the start of a [`&mut [u8]`][`prim@slice`] and returns
+/// how many bytes were written. Returns an error instead of allocating if it doesn't fit.
+///
+/// The output is the same as [`serialize`]'s. Several values can be written one after another by
+/// passing the rest of the slice each time:
+///
+/// ```edition2021
+/// let mut packet = [0; 16];
+/// let mut len = 0;
+/// for i in 0u32.. {
+///     match bitcode::serialize_into_slice(&i, &mut packet[len..]) {
+///         Ok(written) => len += written,
+///         Err(e) if e.kind() == bitcode::ErrorKind::BufferFull => break,
+///         Err(e) => panic!("{e}"),
+///     }
+/// }
+/// assert_eq!(len, 16);
+/// ```
+///
+/// Serializing stops as soon as the slice is full. It doesn't allocate unless a sequence or map
+/// has an unknown length or [`Config::with_columnar_layout`] is set, since those elements are
+/// buffered before they're written.
+///
+/// **Warning:** The format is subject to change between versions.
+pub fn serialize_into_slice<T>(t: &T, slice: &mut [u8]) -> Result<usize>
+where
+    T: Serialize + ?Sized,
+{
+    serialize_into_slice_with(t, slice, &Config::default())
 }
 
 /// Deserializes a [`&[u8]`][`prim@slice`] into an instance of `T:` [`Deserialize`].
@@ -56,63 +156,286 @@ pub fn deserialize<'a, T>(bytes: &'a [u8]) -> Result<T>
 where
     T: Deserialize<'a>,
 {
-    deserialize_with::<'a, T, ReadWithImpl>(bytes)
+    deserialize_with::<T, ReadWithImpl>(bytes, &Config::default())
+}
+
+/// Deserializes the start of a [`&[u8]`][`prim@slice`] into an instance of `T:` [`Deserialize`]
+/// and returns the bytes after it. Unlike [`deserialize`], bytes may be left over, so values can
+/// be embedded in larger messages.
+///
+/// ```edition2021
+/// let mut bytes = bitcode::serialize("abc").unwrap();
+/// bytes.extend_from_slice(&[1, 2, 3]);
+///
+/// let (decoded, rest): (String, &[u8]) = bitcode::deserialize_prefix(&bytes).unwrap();
+/// assert_eq!(decoded, "abc");
+/// assert_eq!(rest, &[1, 2, 3]);
+/// ```
+///
+/// **Warning:** The format is subject to change between versions.
+pub fn deserialize_prefix<'a, T>(bytes: &'a [u8]) -> Result<(T, &'a [u8])>
+where
+    T: Deserialize<'a>,
+{
+    deserialize_prefix_with::<T, ReadWithImpl>(bytes, &Config::default())
+}
+
+/// Deserializes an [`std::io::Read`] into an instance of `T:` [`DeserializeOwned`]. Unlike
+/// [`deserialize`], only a small buffer is kept in memory, so large inputs can be read from files
+/// and sockets. Like [`deserialize`], the whole input must be consumed.
+///
+/// The input is read in chunks, so wrapping `r` in a [`std::io::BufReader`] isn't necessary.
+///
+/// **Warning:** The format is subject to change between versions.
+pub fn deserialize_from_reader<T>(r: impl std::io::Read) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    deserialize_from_reader_with(r, &Config::default())
+}
+
+/// The version of the format, written by [`serialize_versioned`]. It changes whenever a new
+/// version of this crate serializes some value differently.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Serializes a `T:` [`Serialize`] into a [`Vec<u8>`] prefixed with [`FORMAT_VERSION`] and
+/// `schema_version`, so data stored by an older version of this crate or of `T` is rejected
+/// instead of being deserialized as garbage.
+///
+/// The prefix is 8 bytes: the two versions as little-endian [`u32`]s. It stays the same in every
+/// version of this crate. `schema_version` is up to the caller, e.g. it can be increased whenever
+/// `T` changes.
+///
+/// ```edition2021
+/// let encoded = bitcode::serialize_versioned(&"abc", 3).unwrap();
+/// assert_eq!(encoded[..8], [bitcode::FORMAT_VERSION.to_le_bytes(), 3u32.to_le_bytes()].concat());
+///
+/// let decoded: String = bitcode::deserialize_versioned(&encoded, 3).unwrap();
+/// assert_eq!(decoded, "abc");
+///
+/// let error = bitcode::deserialize_versioned::<String>(&encoded, 4).unwrap_err();
+/// assert_eq!(error.kind(), bitcode::ErrorKind::VersionMismatch);
+/// ```
+pub fn serialize_versioned<T>(t: &T, schema_version: u32) -> Result<Vec<u8>>
+where
+    T: Serialize + ?Sized,
+{
+    serialize_versioned_with::<WriteWithImpl>(t, schema_version, &Config::default())
+}
+
+/// Deserializes a [`&[u8]`][`prim@slice`] serialized by [`serialize_versioned`] into an instance
+/// of `T:` [`Deserialize`]. Returns an [`ErrorKind::VersionMismatch`] error without deserializing
+/// anything if the input wasn't serialized with [`FORMAT_VERSION`] and `schema_version`.
+pub fn deserialize_versioned<'a, T>(bytes: &'a [u8], schema_version: u32) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    deserialize_versioned_with::<T, ReadWithImpl>(bytes, schema_version, &Config::default())
+}
+
+/// Encodes a `T:` [`Encode`] into a [`Vec<u8>`] without going through serde.
+///
+/// The output is the same as [`serialize`]'s for types that derive both [`Encode`] and
+/// [`Serialize`], but encoding is faster.
+///
+#[cfg_attr(feature = "derive", doc = "```edition2021")]
+#[cfg_attr(not(feature = "derive"), doc = "```ignore")]
+/// use bitcode::{Decode, Encode};
+///
+/// #[derive(Debug, PartialEq, Encode, Decode)]
+/// struct Foo {
+///     x: u32,
+///     y: Option<String>,
+/// }
+///
+/// let foo = Foo { x: 5, y: Some("abc".to_owned()) };
+/// let encoded: Vec<u8> = bitcode::encode(&foo).unwrap();
+/// let decoded: Foo = bitcode::decode(&encoded).unwrap();
+/// assert_eq!(foo, decoded);
+/// ```
+///
+/// **Warning:** The format is subject to change between versions.
+pub fn encode<T>(t: &T) -> Result<Vec<u8>>
+where
+    T: Encode + ?Sized,
+{
+    let mut w = WriteWithImpl::default();
+    t.encode(&mut w)?;
+    Ok(w.into_inner())
+}
+
+/// Decodes a [`&[u8]`][`prim@slice`] into an instance of `T:` [`Decode`] without going through
+/// serde. See [`encode`].
+///
+/// Like [`deserialize`], it has no depth or allocation limits. Use [`Config::decode`] to decode
+/// untrusted input.
+///
+/// **Warning:** The format is subject to change between versions.
+pub fn decode<T>(bytes: &[u8]) -> Result<T>
+where
+    T: Decode,
+{
+    decode_with(bytes, &Config::default())
 }
 
 /// (De)serialization errors.
 ///
+/// [`Error::kind`] tells what went wrong and [`Error::bit_position`] tells where in the input
+/// deserialization failed.
+///
 /// # Debug mode
 ///
-/// In debug mode, the error contains a reason.
+/// In debug mode, the error also contains a reason.
 ///
 /// # Release mode
 ///
-/// In release mode, the error is a zero-sized type for efficiency.
+/// In release mode, the error only contains its kind and position for efficiency.
 #[derive(Debug)]
-#[cfg_attr(test, derive(PartialEq))]
-pub struct Error(ErrorImpl);
+pub struct Error {
+    // Only messages of custom errors are boxed, so other errors don't allocate.
+    e: ErrorImpl,
+    bit_position: Option<usize>,
+}
 
 #[cfg(not(debug_assertions))]
-type ErrorImpl = ();
+type ErrorImpl = ErrorKind;
 
 #[cfg(debug_assertions)]
 type ErrorImpl = E;
 
 impl Error {
+    fn new(e: ErrorImpl) -> Self {
+        Self {
+            e,
+            bit_position: None,
+        }
+    }
+
+    /// Returns the kind of error.
+    pub fn kind(&self) -> ErrorKind {
+        #[cfg(debug_assertions)]
+        return self.e.kind();
+        #[cfg(not(debug_assertions))]
+        self.e
+    }
+
+    /// Returns the number of bits of the input that were read before deserialization failed or
+    /// [`None`] if the error didn't occur while deserializing.
+    ///
+    /// With [`Config::with_columnar_layout`], it's the position in the input of the bit of the
+    /// column that was being read, even though columns aren't read in order.
+    pub fn bit_position(&self) -> Option<usize> {
+        self.bit_position
+    }
+
+    /// Sets the position returned by [`Error::bit_position`] if it isn't already set.
+    pub(crate) fn at_bit(mut self, bit_position: usize) -> Self {
+        self.bit_position.get_or_insert(bit_position);
+        self
+    }
+
     /// Replaces an invalid message. E.g. read_variant_index calls read_len but converts
     /// `E::Invalid("length")` to `E::Invalid("variant index")`.
-    pub(crate) fn map_invalid(self, _s: &'static str) -> Self {
+    #[cfg_attr(not(debug_assertions), allow(unused_mut))]
+    pub(crate) fn map_invalid(mut self, _s: &'static str) -> Self {
         #[cfg(debug_assertions)]
-        return Self(match self.0 {
-            E::Invalid(_) => E::Invalid(_s),
-            _ => self.0,
-        });
-        #[cfg(not(debug_assertions))]
+        if let E::Invalid(s) = &mut self.e {
+            *s = _s;
+        }
         self
     }
 
     pub(crate) fn same(&self, other: &Self) -> bool {
-        self.0 == other.0
+        self.e == other.e
+    }
+}
+
+// Ignores the bit position so tests can compare errors to `E::...e()`.
+#[cfg(test)]
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.same(other)
+    }
+}
+
+/// The kind of an [`Error`], returned by [`Error::kind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The output doesn't fit in the buffer passed to [`serialize_into_slice`].
+    BufferFull,
+    /// An error from a [`Serialize`] or [`Deserialize`] implementation.
+    Custom,
+    /// The input ended before deserialization finished.
+    Eof,
+    /// The input has bytes left over after deserialization finished.
+    ExpectedEof,
+    /// The input contains an invalid value, such as a string that isn't UTF-8 or a variant index
+    /// that is out of range.
+    Invalid,
+    /// An [`std::io::Read`] or [`std::io::Write`] returned an error.
+    Io,
+    /// A limit set with [`Config`] was exceeded.
+    LimitExceeded,
+    /// The type uses a serde feature that isn't supported.
+    NotSupported,
+    /// The input of [`deserialize_versioned`] was serialized with a different format version or
+    /// schema version.
+    VersionMismatch,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::BufferFull => "buffer full",
+            Self::Custom => "custom",
+            Self::Eof => "eof",
+            Self::ExpectedEof => "expected eof",
+            Self::Invalid => "invalid",
+            Self::Io => "io",
+            Self::LimitExceeded => "limit exceeded",
+            Self::NotSupported => "not supported",
+            Self::VersionMismatch => "version mismatch",
+        })
     }
 }
 
 /// Inner error that can be converted to [`Error`] with [`E::e`].
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum E {
+    BufferFull,
     #[cfg(debug_assertions)]
-    Custom(String),
+    Custom(Box<str>),
     Eof,
     ExpectedEof,
     Invalid(&'static str),
+    Io(std::io::ErrorKind),
+    LimitExceeded(&'static str),
     NotSupported(&'static str),
+    VersionMismatch(&'static str),
 }
 
 impl E {
     fn e(self) -> Error {
         #[cfg(debug_assertions)]
-        return Error(self);
+        return Error::new(self);
         #[cfg(not(debug_assertions))]
-        Error(())
+        Error::new(self.kind())
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self {
+            #[cfg(debug_assertions)]
+            Self::Custom(_) => ErrorKind::Custom,
+            Self::BufferFull => ErrorKind::BufferFull,
+            Self::Eof => ErrorKind::Eof,
+            Self::ExpectedEof => ErrorKind::ExpectedEof,
+            Self::Invalid(_) => ErrorKind::Invalid,
+            Self::Io(_) => ErrorKind::Io,
+            Self::LimitExceeded(_) => ErrorKind::LimitExceeded,
+            Self::NotSupported(_) => ErrorKind::NotSupported,
+            Self::VersionMismatch(_) => ErrorKind::VersionMismatch,
+        }
     }
 }
 
@@ -120,10 +443,11 @@ type Result<T> = std::result::Result<T, Error>;
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        #[cfg(debug_assertions)]
-        return Display::fmt(&self.0, f);
-        #[cfg(not(debug_assertions))]
-        f.write_str("bitcode error")
+        Display::fmt(&self.e, f)?;
+        if let Some(bit_position) = self.bit_position {
+            write!(f, " at bit {bit_position}")?;
+        }
+        Ok(())
     }
 }
 
@@ -132,25 +456,35 @@ impl Display for E {
         match self {
             #[cfg(debug_assertions)]
             Self::Custom(s) => write!(f, "custom: {s}"),
+            Self::BufferFull => write!(f, "buffer full"),
             Self::Eof => write!(f, "eof"),
             Self::ExpectedEof => write!(f, "expected eof"),
             Self::Invalid(s) => write!(f, "invalid {s}"),
+            Self::Io(kind) => write!(f, "io: {kind}"),
+            Self::LimitExceeded(s) => write!(f, "{s} limit exceeded"),
             Self::NotSupported(s) => write!(f, "{s} is not supported"),
+            Self::VersionMismatch(s) => write!(f, "{s} version mismatch"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        E::Io(e.kind()).e()
+    }
+}
+
 impl serde::ser::Error for Error {
     fn custom<T>(_msg: T) -> Self
     where
         T: Display,
     {
         #[cfg(debug_assertions)]
-        return Self(E::Custom(_msg.to_string()));
+        return E::Custom(_msg.to_string().into_boxed_str()).e();
         #[cfg(not(debug_assertions))]
-        Self(())
+        Self::new(ErrorKind::Custom)
     }
 }
 
@@ -160,8 +494,8 @@ impl serde::de::Error for Error {
         T: Display,
     {
         #[cfg(debug_assertions)]
-        return Self(E::Custom(_msg.to_string()));
+        return E::Custom(_msg.to_string().into_boxed_str()).e();
         #[cfg(not(debug_assertions))]
-        Self(())
+        Self::new(ErrorKind::Custom)
     }
 }
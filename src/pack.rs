@@ -0,0 +1,119 @@
+use crate::de::read::{LimitRead, ReadWith, ReadWithImpl};
+use crate::de::{deserialize_partial, finish};
+use crate::ser::serialize_into;
+use crate::ser::write::{LimitWrite, WriteWith, WriteWithImpl};
+use crate::{Config, Result};
+use serde::{Deserialize, Serialize};
+
+/// Serializes many values back to back into one bit stream. Unlike concatenating the outputs of
+/// [`serialize`][`crate::serialize`], values aren't padded to whole bytes, so only the end of the
+/// stream is. Read them back with an [`Unpacker`].
+///
+/// ```edition2021
+/// let mut packer = bitcode::Packer::new();
+/// for i in 0..10 {
+///     packer.pack(&(i % 2 == 0)).unwrap();
+/// }
+/// let packed: Vec<u8> = packer.finish().to_vec();
+/// assert_eq!(packed.len(), 2);
+///
+/// let mut unpacker = bitcode::Unpacker::new(&packed);
+/// for i in 0..10 {
+///     assert_eq!(unpacker.unpack::<bool>().unwrap(), i % 2 == 0);
+/// }
+/// unpacker.finish().unwrap();
+/// ```
+#[derive(Default)]
+pub struct Packer {
+    data: WriteWithImpl,
+    config: Config,
+}
+
+impl Packer {
+    /// Creates an empty [`Packer`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty [`Packer`] that serializes with a [`Config`].
+    pub fn with_config(config: Config) -> Self {
+        Self {
+            data: Default::default(),
+            config,
+        }
+    }
+
+    /// Serializes a `T:` [`Serialize`] after the values packed so far. A limit set with
+    /// [`Config::with_limit`] applies to each value, rounded up to a whole byte.
+    ///
+    /// If an error is returned, part of the value might have been packed already, so the
+    /// [`Packer`] should be cleared.
+    pub fn pack<T>(&mut self, t: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        let w = LimitWrite::new(&mut self.data, self.config.limit);
+        serialize_into(t, w, &self.config)?.finish()
+    }
+
+    /// Returns the values packed so far, padded to a whole byte. More values can be packed after
+    /// it, but the padding isn't kept, so they start in its last byte (which changes).
+    ///
+    /// A limit set with [`Config::with_limit`] was already checked for each value by
+    /// [`Packer::pack`], so it doesn't apply to the whole stream.
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn finish(&mut self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// Removes all the values packed so far while keeping the allocation.
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+/// Deserializes values packed by a [`Packer`] one after another.
+///
+/// The padding at the end of the stream is only checked by [`Unpacker::finish`]. Since it's made of
+/// zero bits, unpacking more values than were packed might succeed instead of returning an error,
+/// so the number of values should be known (e.g. packed first).
+pub struct Unpacker<'a> {
+    data: ReadWithImpl<'a>,
+    len: usize,
+    config: Config,
+}
+
+impl<'a> Unpacker<'a> {
+    /// Creates an [`Unpacker`] that reads the values packed in `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self::with_config(bytes, Config::default())
+    }
+
+    /// Creates an [`Unpacker`] that reads the values packed in `bytes` with a [`Config`].
+    pub fn with_config(bytes: &'a [u8], config: Config) -> Self {
+        Self {
+            data: ReadWithImpl::from_inner(bytes),
+            len: bytes.len(),
+            config,
+        }
+    }
+
+    /// Deserializes the next value into an instance of `T:` [`Deserialize`]. A limit set with
+    /// [`Config::with_limit`] applies to each value, rounded up to a whole byte, and stops reading
+    /// once it's exceeded.
+    ///
+    /// **Warning:** The format is subject to change between versions.
+    pub fn unpack<T>(&mut self) -> Result<T>
+    where
+        T: Deserialize<'a>,
+    {
+        let r = LimitRead::new(&mut self.data, self.config.limit);
+        deserialize_partial(r, self.len, &self.config)
+    }
+
+    /// Checks that all the values were unpacked and that the padding at the end is zero.
+    pub fn finish(mut self) -> Result<()> {
+        finish(&mut self.data, &self.config)
+    }
+}
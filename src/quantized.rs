@@ -0,0 +1,445 @@
+use crate::bits;
+use serde::de::{Error as _, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::f64::consts::TAU;
+use std::marker::PhantomData;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A float that can be quantized, [`f32`] or [`f64`].
+pub trait QuantizedFloat: Copy + private::Sealed {
+    #[doc(hidden)]
+    fn to_f64(self) -> f64;
+    #[doc(hidden)]
+    fn from_f64(v: f64) -> Self;
+}
+
+impl private::Sealed for f32 {}
+
+impl QuantizedFloat for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(v: f64) -> Self {
+        v as f32
+    }
+}
+
+impl private::Sealed for f64 {}
+
+impl QuantizedFloat for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(v: f64) -> Self {
+        v
+    }
+}
+
+/// How values between two points of the grid are quantized.
+pub trait Rounding: private::Sealed {
+    /// The worst-case error in steps of the grid.
+    #[doc(hidden)]
+    const MAX_ERROR_STEPS: f64;
+    #[doc(hidden)]
+    fn round(x: f64) -> f64;
+}
+
+/// Rounds to the nearest point of the grid, which halves the worst-case error (the default).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RoundNearest;
+
+/// Rounds down to the point of the grid below, so the decoded value is never greater.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RoundDown;
+
+/// Rounds up to the point of the grid above, so the decoded value is never less.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RoundUp;
+
+macro_rules! impl_rounding {
+    ($t:ty, $steps:literal, $round:ident) => {
+        impl private::Sealed for $t {}
+
+        impl Rounding for $t {
+            const MAX_ERROR_STEPS: f64 = $steps;
+
+            fn round(x: f64) -> f64 {
+                x.$round()
+            }
+        }
+    };
+}
+
+impl_rounding!(RoundNearest, 0.5, round);
+impl_rounding!(RoundDown, 1.0, floor);
+impl_rounding!(RoundUp, 1.0, ceil);
+
+/// A float in range `MIN..=MAX` that's (de)serialized with `BITS` bits by mapping it to the
+/// nearest (see [`Rounding`]) of `2^BITS - 1` evenly spaced values, including `MIN`, `MAX` and
+/// the value halfway between them, so e.g. 0 is exact in range `-1..=1`. With 1 bit the values
+/// are just `MIN` and `MAX`.
+///
+/// `MIN` and `MAX` are integers since floats can't be const generics. `BITS` must be in range
+/// `1..=64` and `MIN` must be less than `MAX`. Values outside the range are clamped to it and NaN
+/// becomes `MIN`.
+///
+/// ```edition2021
+/// use bitcode::{Quantized, RoundDown};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Player {
+///     x: Quantized<f32, -1000, 1000, 16>,
+///     health: Quantized<f32, 0, 1, 7, RoundDown>,
+/// }
+///
+/// let player = Player {
+///     x: Quantized::new(-123.45),
+///     health: Quantized::new(0.5),
+/// };
+/// assert_eq!(bitcode::serialized_size_bits(&player).unwrap(), 23);
+///
+/// let encoded = bitcode::serialize(&player).unwrap();
+/// let decoded: Player = bitcode::deserialize(&encoded).unwrap();
+///
+/// let max_error = Quantized::<f32, -1000, 1000, 16>::max_error();
+/// assert!((decoded.x.get() - -123.45).abs() as f64 <= max_error + 1e-4);
+/// assert!(decoded.health.get() <= 0.5);
+/// ```
+///
+/// Other serde formats see a tuple struct containing a [`u64`].
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Quantized<T, const MIN: i64, const MAX: i64, const BITS: usize, R = RoundNearest> {
+    value: T,
+    rounding: PhantomData<R>,
+}
+
+impl<T: QuantizedFloat, const MIN: i64, const MAX: i64, const BITS: usize, R: Rounding>
+    Quantized<T, MIN, MAX, BITS, R>
+{
+    const VALID: () = assert!(
+        MIN < MAX && BITS >= 1 && BITS <= 64,
+        "Quantized requires MIN < MAX and 1 <= BITS <= 64"
+    );
+
+    /// The greatest quantized value. It's even (except with 1 bit) so the middle of the range is
+    /// a level and the greatest `BITS` bit value is unused.
+    const MAX_LEVEL: u64 = match BITS {
+        1 => 1,
+        _ => (u64::MAX >> (u64::BITS as usize - BITS)) - 1,
+    };
+
+    /// Wraps `value`, which isn't quantized until it's serialized.
+    pub fn new(value: T) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        Self {
+            value,
+            rounding: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped value.
+    pub fn get(self) -> T {
+        self.value
+    }
+
+    /// Returns the wrapped value as it will be after being serialized and deserialized.
+    pub fn quantized(self) -> T {
+        Self::dequantize(Self::quantize(self.value))
+    }
+
+    /// Returns the distance between two consecutive values of the grid.
+    pub fn step() -> f64 {
+        (MAX as f64 - MIN as f64) / Self::MAX_LEVEL as f64
+    }
+
+    /// Returns the worst-case difference between a value in range `MIN..=MAX` and the value it
+    /// decodes to, not counting the rounding of the decoded value to `T`.
+    pub fn max_error() -> f64 {
+        Self::step() * R::MAX_ERROR_STEPS
+    }
+
+    fn quantize(value: T) -> u64 {
+        let levels = Self::MAX_LEVEL as f64;
+        let x = (value.to_f64() - MIN as f64) / (MAX as f64 - MIN as f64) * levels;
+        // NaN casts to 0.
+        (R::round(x.clamp(0.0, levels)) as u64).min(Self::MAX_LEVEL)
+    }
+
+    fn dequantize(level: u64) -> T {
+        let t = level as f64 / Self::MAX_LEVEL as f64;
+        // Lerp that returns exactly MAX for the greatest level.
+        T::from_f64(MIN as f64 * (1.0 - t) + MAX as f64 * t)
+    }
+}
+
+impl<T, const MIN: i64, const MAX: i64, const BITS: usize, R> Default
+    for Quantized<T, MIN, MAX, BITS, R>
+where
+    T: QuantizedFloat + Default,
+    R: Rounding,
+{
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: QuantizedFloat, const MIN: i64, const MAX: i64, const BITS: usize, R: Rounding> From<T>
+    for Quantized<T, MIN, MAX, BITS, R>
+{
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: QuantizedFloat, const MIN: i64, const MAX: i64, const BITS: usize, R: Rounding> Serialize
+    for Quantized<T, MIN, MAX, BITS, R>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        bits::<BITS>::serialize(&Self::quantize(self.value), serializer)
+    }
+}
+
+impl<'de, T: QuantizedFloat, const MIN: i64, const MAX: i64, const BITS: usize, R: Rounding>
+    Deserialize<'de> for Quantized<T, MIN, MAX, BITS, R>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let level: u64 = bits::<BITS>::deserialize(deserializer)?;
+        if level > Self::MAX_LEVEL {
+            return Err(D::Error::invalid_value(
+                Unexpected::Unsigned(level),
+                &"a quantized value",
+            ));
+        }
+        Ok(Self::new(Self::dequantize(level)))
+    }
+}
+
+/// An angle in radians that's (de)serialized with `BITS` bits by mapping it to the nearest (see
+/// [`Rounding`]) of `2^BITS` evenly spaced angles in range `0..TAU`.
+///
+/// Angles are wrapped to `0..TAU`, so the decoded angle of `-PI / 2.0` is `3.0 * PI / 2.0`.
+/// `BITS` must be in range `1..=63`. NaN becomes 0.
+///
+/// ```edition2021
+/// use bitcode::QuantizedAngle;
+/// use std::f32::consts::PI;
+///
+/// let angle = QuantizedAngle::<f32, 8>::new(PI / 3.0);
+/// assert_eq!(bitcode::serialized_size_bits(&angle).unwrap(), 8);
+///
+/// let encoded = bitcode::serialize(&angle).unwrap();
+/// let decoded: QuantizedAngle<f32, 8> = bitcode::deserialize(&encoded).unwrap();
+/// let max_error = QuantizedAngle::<f32, 8>::max_error();
+/// assert!((decoded.get() - PI / 3.0).abs() as f64 <= max_error + 1e-6);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct QuantizedAngle<T, const BITS: usize, R = RoundNearest> {
+    value: T,
+    rounding: PhantomData<R>,
+}
+
+impl<T: QuantizedFloat, const BITS: usize, R: Rounding> QuantizedAngle<T, BITS, R> {
+    const VALID: () = assert!(
+        BITS >= 1 && BITS <= 63,
+        "QuantizedAngle requires 1 <= BITS <= 63"
+    );
+
+    const LEVELS: u64 = 1 << BITS;
+
+    /// Wraps `value`, which isn't quantized until it's serialized.
+    pub fn new(value: T) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+        Self {
+            value,
+            rounding: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped value.
+    pub fn get(self) -> T {
+        self.value
+    }
+
+    /// Returns the wrapped value as it will be after being serialized and deserialized.
+    pub fn quantized(self) -> T {
+        Self::dequantize(Self::quantize(self.value))
+    }
+
+    /// Returns the angle between two consecutive angles of the grid.
+    pub fn step() -> f64 {
+        TAU / Self::LEVELS as f64
+    }
+
+    /// Returns the worst-case angle between an angle and the angle it decodes to, not counting
+    /// the rounding of the decoded angle to `T`.
+    pub fn max_error() -> f64 {
+        Self::step() * R::MAX_ERROR_STEPS
+    }
+
+    fn quantize(value: T) -> u64 {
+        let x = value.to_f64().rem_euclid(TAU) / TAU * Self::LEVELS as f64;
+        // TAU wraps around to 0 and NaN casts to 0.
+        R::round(x) as u64 % Self::LEVELS
+    }
+
+    fn dequantize(level: u64) -> T {
+        T::from_f64(level as f64 * Self::step())
+    }
+}
+
+impl<T: QuantizedFloat + Default, const BITS: usize, R: Rounding> Default
+    for QuantizedAngle<T, BITS, R>
+{
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: QuantizedFloat, const BITS: usize, R: Rounding> From<T> for QuantizedAngle<T, BITS, R> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: QuantizedFloat, const BITS: usize, R: Rounding> Serialize for QuantizedAngle<T, BITS, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        bits::<BITS>::serialize(&Self::quantize(self.value), serializer)
+    }
+}
+
+impl<'de, T: QuantizedFloat, const BITS: usize, R: Rounding> Deserialize<'de>
+    for QuantizedAngle<T, BITS, R>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let level: u64 = bits::<BITS>::deserialize(deserializer)?;
+        Ok(Self::new(Self::dequantize(level)))
+    }
+}
+
+/// A 3D unit vector that's (de)serialized with `2 * BITS` bits using
+/// [octahedral encoding](https://jcgt.org/published/0003/02/01/). The 2 coordinates of the
+/// vector's point on the octahedron are stored as [`Quantized`] values in range `-1..=1`.
+///
+/// Vectors are normalized before being quantized and decode to unit vectors. `BITS` must be in
+/// range `1..=64`. The zero vector and vectors containing NaN decode to an unspecified unit
+/// vector.
+///
+/// ```edition2021
+/// use bitcode::QuantizedUnitVector;
+///
+/// let up = QuantizedUnitVector::<f32, 10>::new([0.0, 1.0, 0.0]);
+/// assert_eq!(bitcode::serialized_size_bits(&up).unwrap(), 20);
+///
+/// let encoded = bitcode::serialize(&up).unwrap();
+/// let decoded: QuantizedUnitVector<f32, 10> = bitcode::deserialize(&encoded).unwrap();
+/// let [x, y, z] = decoded.get();
+/// let distance = (x * x + (y - 1.0) * (y - 1.0) + z * z).sqrt();
+/// assert!(distance as f64 <= QuantizedUnitVector::<f32, 10>::max_error() + 1e-6);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct QuantizedUnitVector<T, const BITS: usize, R = RoundNearest> {
+    value: [T; 3],
+    rounding: PhantomData<R>,
+}
+
+impl<T: QuantizedFloat, const BITS: usize, R: Rounding> QuantizedUnitVector<T, BITS, R> {
+    /// Wraps `value`, which isn't quantized until it's serialized.
+    pub fn new(value: [T; 3]) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Quantized::<T, -1, 1, BITS, R>::VALID;
+        Self {
+            value,
+            rounding: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped value.
+    pub fn get(self) -> [T; 3] {
+        self.value
+    }
+
+    /// Returns the wrapped value as it will be after being serialized and deserialized.
+    pub fn quantized(self) -> [T; 3] {
+        let (u, v) = Self::quantize(self.value);
+        Self::dequantize(u.quantized(), v.quantized())
+    }
+
+    /// Returns the worst-case distance between a unit vector and the vector it decodes to, not
+    /// counting the rounding of the decoded vector to `T`.
+    pub fn max_error() -> f64 {
+        // Moving a coordinate by e moves the point on the octahedron by at most sqrt(6) * e.
+        // Normalizing points at least 1 / sqrt(3) from the origin multiplies that by sqrt(3).
+        3.0 * std::f64::consts::SQRT_2 * Quantized::<T, -1, 1, BITS, R>::max_error()
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn quantize(value: [T; 3]) -> (Quantized<T, -1, 1, BITS, R>, Quantized<T, -1, 1, BITS, R>) {
+        let [x, y, z] = value.map(T::to_f64);
+        let l1 = x.abs() + y.abs() + z.abs();
+        let [x, y, z] = [x / l1, y / l1, z / l1];
+        let (u, v) = if z >= 0.0 {
+            (x, y)
+        } else {
+            // Fold the lower half of the octahedron over the upper half.
+            ((1.0 - y.abs()) * x.signum(), (1.0 - x.abs()) * y.signum())
+        };
+        (
+            Quantized::new(T::from_f64(u)),
+            Quantized::new(T::from_f64(v)),
+        )
+    }
+
+    fn dequantize(u: T, v: T) -> [T; 3] {
+        let [u, v] = [u, v].map(T::to_f64);
+        let z = 1.0 - u.abs() - v.abs();
+        let (x, y) = if z >= 0.0 {
+            (u, v)
+        } else {
+            ((1.0 - v.abs()) * u.signum(), (1.0 - u.abs()) * v.signum())
+        };
+        let l2 = (x * x + y * y + z * z).sqrt();
+        [x / l2, y / l2, z / l2].map(T::from_f64)
+    }
+}
+
+impl<T: QuantizedFloat + Default, const BITS: usize, R: Rounding> Default
+    for QuantizedUnitVector<T, BITS, R>
+{
+    fn default() -> Self {
+        Self::new([T::default(); 3])
+    }
+}
+
+impl<T: QuantizedFloat, const BITS: usize, R: Rounding> From<[T; 3]>
+    for QuantizedUnitVector<T, BITS, R>
+{
+    fn from(value: [T; 3]) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: QuantizedFloat, const BITS: usize, R: Rounding> Serialize
+    for QuantizedUnitVector<T, BITS, R>
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::quantize(self.value).serialize(serializer)
+    }
+}
+
+impl<'de, T: QuantizedFloat, const BITS: usize, R: Rounding> Deserialize<'de>
+    for QuantizedUnitVector<T, BITS, R>
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (u, v): (Quantized<T, -1, 1, BITS, R>, Quantized<T, -1, 1, BITS, R>) =
+            Deserialize::deserialize(deserializer)?;
+        Ok(Self::new(Self::dequantize(u.get(), v.get())))
+    }
+}
@@ -1,5 +1,7 @@
+use crate::fixed_bits::{bits_int_bits, fits};
 use crate::nightly::ilog2;
-use crate::{Error, Result, E};
+use crate::tag::{Tag, BITS_LEN_BITS};
+use crate::{Config, Error, Result, E, FORMAT_VERSION};
 use serde::ser::{
     SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
     SerializeTupleStruct, SerializeTupleVariant,
@@ -7,96 +9,433 @@ use serde::ser::{
 use serde::{Serialize, Serializer};
 
 pub(crate) mod write;
-use write::{Write, WriteWith};
+use write::{ColumnsWrite, CountWrite, IoWrite, LenBuffer, SliceWrite, Write, WriteWith};
+
+pub(crate) fn serialize_with<T: WriteWith>(
+    t: &(impl Serialize + ?Sized),
+    config: &Config,
+) -> Result<Vec<u8>> {
+    let bytes = serialize_into(t, T::default(), config)?.into_inner();
+    config.check_limit(bytes.len())?;
+    Ok(bytes)
+}
+
+/// Like [`serialize_with`] but prefixes the output with [`FORMAT_VERSION`] and `schema_version`.
+pub(crate) fn serialize_versioned_with<T: WriteWith>(
+    t: &(impl Serialize + ?Sized),
+    schema_version: u32,
+    config: &Config,
+) -> Result<Vec<u8>> {
+    let mut w = T::default();
+    w.write_bits(FORMAT_VERSION as u64, u32::BITS as usize);
+    w.write_bits(schema_version as u64, u32::BITS as usize);
+    let bytes = serialize_into(t, w, config)?.into_inner();
+    config.check_limit(bytes.len())?;
+    Ok(bytes)
+}
+
+/// Like [`serialize_with`] but reuses `w`'s allocation.
+pub(crate) fn serialize_in<'a, T: WriteWith>(
+    t: &(impl Serialize + ?Sized),
+    w: &'a mut T,
+    config: &Config,
+) -> Result<&'a [u8]> {
+    w.clear();
+    serialize_into(t, &mut *w, config)?;
+    let bytes = w.as_slice();
+    config.check_limit(bytes.len())?;
+    Ok(bytes)
+}
+
+/// Like [`serialize_with`] but writes to an [`std::io::Write`] as it goes.
+pub(crate) fn serialize_into_writer_with(
+    t: &(impl Serialize + ?Sized),
+    w: impl std::io::Write,
+    config: &Config,
+) -> Result<()> {
+    serialize_into(t, IoWrite::new(w, config.limit), config)?.finish()?;
+    Ok(())
+}
+
+/// Returns how many bits [`serialize_with`] would write before padding them to a byte.
+pub(crate) fn serialized_size_bits_with(
+    t: &(impl Serialize + ?Sized),
+    config: &Config,
+) -> Result<usize> {
+    Ok(serialize_into(t, CountWrite::default(), config)?.bits)
+}
 
-pub(crate) fn serialize_with<T: WriteWith>(t: &(impl Serialize + ?Sized)) -> Result<Vec<u8>> {
-    Ok(serialize_into(t, T::default())?.into_inner())
+/// Like [`serialize_with`] but writes to the start of `slice` and returns how many bytes were
+/// written.
+pub(crate) fn serialize_into_slice_with(
+    t: &(impl Serialize + ?Sized),
+    slice: &mut [u8],
+    config: &Config,
+) -> Result<usize> {
+    let len = serialize_into(t, SliceWrite::new(slice), config)?.finish()?;
+    config.check_limit(len)?;
+    Ok(len)
 }
 
-pub(crate) fn serialize_into<W: Write>(t: &(impl Serialize + ?Sized), w: W) -> Result<W> {
-    let mut s = BitcodeSerializer { data: w };
+/// The maximum length in bytes of a prefix written by [`serialize_len_prefix`] (an Elias gamma
+/// code of up to 127 bits).
+#[cfg(feature = "tokio")]
+pub(crate) const MAX_LEN_PREFIX: usize = 16;
+
+/// Serializes `len` like the length of a sequence, padded to a byte, to the start of `out`.
+/// Returns how many bytes it took up, which are at most [`MAX_LEN_PREFIX`].
+#[cfg(feature = "tokio")]
+pub(crate) fn serialize_len_prefix(len: usize, out: &mut [u8], config: &Config) -> Result<usize> {
+    let mut s = BitcodeSerializer::new(write::SliceWrite::new(out), config);
+    s.serialize_len(len)?;
+    s.data.finish()
+}
+
+pub(crate) fn serialize_into<W: Write>(
+    t: &(impl Serialize + ?Sized),
+    w: W,
+    config: &Config,
+) -> Result<W> {
+    let mut s = BitcodeSerializer::new(w, config);
     t.serialize(&mut s)?;
     Ok(s.data)
 }
 
-#[derive(Default)]
-struct BitcodeSerializer<W> {
+struct BitcodeSerializer<'c, W> {
     data: W,
+    config: &'c Config,
+    /// Remaining levels of nesting before [`Config::with_max_depth`] is exceeded.
+    depth_budget: usize,
+    /// If `data` is the [`ColumnsWrite`] of a sequence with [`Config::with_columnar_layout`].
+    in_columns: bool,
+    /// The number of bits of the next [`u64`] if it's serialized by [`bits`][`crate::bits`].
+    bits: Option<usize>,
+    /// Struct fields skipped with [`Config::with_skippable_fields`] since the last one that was
+    /// serialized. They're only written once a field follows them.
+    skipped_fields: usize,
+}
+
+/// Writes `len` with [gamma](https://en.wikipedia.org/wiki/Elias_gamma_coding) encoding.
+#[inline(always)]
+pub(crate) fn write_gamma(w: &mut impl Write, len: usize) -> Result<()> {
+    // Gamma can't encode 0 so add 1. We don't support usize::MAX because it would add more code
+    // and it's only useful for ZST.
+    let v = len
+        .checked_add(1)
+        .ok_or(E::NotSupported("len must be < usize::MAX").e())?;
+
+    let zeros = ilog2(v) as usize;
+    let bit_count = zeros * 2 + 1;
+
+    if bit_count <= 64 {
+        let bits = (v as u64).reverse_bits() >> (u64::BITS as usize - bit_count);
+        w.write_bits(bits, bit_count);
+    } else {
+        #[cold]
+        fn slow(w: &mut impl Write, v: usize) {
+            let zeros = ilog2(v) as usize;
+            w.write_bits(0, zeros);
+
+            let integer_bits = zeros + 1;
+            let lz = usize::BITS as usize - integer_bits;
+
+            let bits = (v.reverse_bits() >> lz) as u64;
+            w.write_bits(bits, integer_bits);
+        }
+
+        slow(w, v);
+    }
+    Ok(())
+}
+
+/// Returns how many bits the variant indices of an enum with `count` variants take with
+/// [`Config::with_variant_count`].
+pub(crate) fn variant_index_bits(count: usize) -> usize {
+    match count {
+        0 | 1 => 0,
+        _ => ilog2(count - 1) as usize + 1,
+    }
+}
+
+/// The slot (see [`Write::enter_path`]) of the fields of an enum variant.
+pub(crate) fn variant_slot(variant_index: u32) -> u64 {
+    (variant_index as u64) << u32::BITS
 }
 
-impl<W: Write> BitcodeSerializer<W> {
+impl<'c, W: Write> BitcodeSerializer<'c, W> {
+    fn new(data: W, config: &'c Config) -> Self {
+        Self {
+            data,
+            config,
+            depth_budget: config.max_depth.unwrap_or(usize::MAX),
+            in_columns: false,
+            bits: None,
+            skipped_fields: 0,
+        }
+    }
+
+    /// Enters a nested value at `slot`. Must be followed by [`Self::exit`] once the value is
+    /// serialized.
+    fn enter(&mut self, slot: u64) -> Result<()> {
+        self.data.check()?;
+        self.depth_budget = self
+            .depth_budget
+            .checked_sub(1)
+            .ok_or(E::LimitExceeded("depth").e())?;
+        self.data.enter_path(slot);
+        Ok(())
+    }
+
+    fn exit(&mut self) {
+        self.depth_budget += 1;
+        self.data.exit_path();
+    }
+
     fn serialize_len(&mut self, len: usize) -> Result<()> {
-        // https://en.wikipedia.org/wiki/Elias_gamma_coding
-        // Gamma can't encode 0 so add 1. We don't support usize::MAX because it would add more code
-        // and it's only useful for ZST.
-        let v = len
-            .checked_add(1)
-            .ok_or(E::NotSupported("len must be < usize::MAX").e())?;
-
-        let zeros = ilog2(v) as usize;
-        let bit_count = zeros * 2 + 1;
-
-        if bit_count <= 64 {
-            let bits = (v as u64).reverse_bits() >> (u64::BITS as usize - bit_count);
-            self.data.write_bits(bits, bit_count);
+        if self.config.varint_lengths {
+            self.serialize_varint(len as u64)
         } else {
-            #[cold]
-            fn slow(me: &mut BitcodeSerializer<impl Write>, v: usize) {
-                let zeros = ilog2(v) as usize;
-                me.data.write_bits(0, zeros);
+            self.serialize_gamma(len)
+        }
+    }
+
+    fn serialize_gamma(&mut self, len: usize) -> Result<()> {
+        write_gamma(&mut self.data, len)
+    }
+
+    /// Writes `tag` if [`Config::with_self_describing_encoding`] is set.
+    fn write_tag(&mut self, tag: Tag) {
+        if self.config.self_describing {
+            tag.write(&mut self.data);
+        }
+    }
+
+    /// Writes the number of fields of a tuple if
+    /// [`Config::with_self_describing_encoding`] is set.
+    fn serialize_tuple_len(&mut self, len: usize) -> Result<()> {
+        if self.config.self_describing {
+            Tag::Tuple.write(&mut self.data);
+            self.serialize_len(len)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the `tag` and index of an enum variant.
+    fn serialize_variant(&mut self, tag: Tag, name: &str, variant_index: u32) -> Result<()> {
+        if self.config.self_describing {
+            // deserialize_any doesn't know the name of the enum to look up its variant count.
+            tag.write(&mut self.data);
+            return self.serialize_gamma(variant_index as usize);
+        }
+        self.serialize_variant_index(name, variant_index)
+    }
+
+    fn serialize_variant_index(&mut self, name: &str, variant_index: u32) -> Result<()> {
+        let Some(count) = self.config.variant_count(name) else {
+            return self.serialize_gamma(variant_index as usize);
+        };
+        if variant_index >= count {
+            return Err(E::Invalid("variant index").e());
+        }
+        let bits = variant_index_bits(count as usize);
+        if bits != 0 {
+            self.data.write_bits(variant_index as u64, bits);
+        }
+        Ok(())
+    }
 
-                let integer_bits = zeros + 1;
-                let lz = usize::BITS as usize - integer_bits;
+    #[inline(never)] // Removing this makes bench_bitcode_serialize 7% slower.
+    fn serialize_len_and_bytes(&mut self, v: &[u8]) -> Result<()> {
+        if v.len() > isize::MAX as usize / u8::BITS as usize {
+            return Err(E::NotSupported("bytes.len() must be < isize::MAX / u8::BITS").e());
+        }
+        self.serialize_len(v.len())?;
+        if self.config.aligned_strings {
+            self.data.align_to_byte();
+        }
+        self.data.write_bytes(v);
+        Ok(())
+    }
+
+    /// Writes the number of fields of a struct that aren't skipped if
+    /// [`Config::with_self_describing_encoding`] or [`Config::with_skippable_fields`] is set.
+    fn serialize_fields_len(&mut self, len: usize) -> Result<()> {
+        if self.config.self_describing || self.config.skippable_fields {
+            self.serialize_len(len)?;
+        }
+        Ok(())
+    }
 
-                let bits = (v.reverse_bits() >> lz) as u64;
-                me.data.write_bits(bits, integer_bits);
+    /// Writes what precedes a struct field: its name if [`Config::with_self_describing_encoding`]
+    /// is set or a 0 bit per field skipped before it and a 1 bit if
+    /// [`Config::with_skippable_fields`] is set.
+    fn serialize_field_prefix(&mut self, key: &str) -> Result<()> {
+        if self.config.self_describing {
+            self.serialize_len_and_bytes(key.as_bytes())?;
+        } else if self.config.skippable_fields {
+            for _ in 0..std::mem::take(&mut self.skipped_fields) {
+                self.data.write_bit(false);
+                self.data.next_path();
             }
+            self.data.write_bit(true);
+        }
+        Ok(())
+    }
+
+    /// Skips a struct field. Self-describing structs omit it since their fields are read by name
+    /// and their number of fields excludes it. Skippable fields are counted until a field follows
+    /// them.
+    fn skip_struct_field(&mut self) -> Result<()> {
+        if self.config.self_describing {
+            Ok(())
+        } else if self.config.skippable_fields {
+            self.skipped_fields += 1;
+            Ok(())
+        } else {
+            Err(E::NotSupported("skip_field").e())
+        }
+    }
+
+    /// Ends a struct. Fields skipped after its last serialized field aren't written since the
+    /// number of fields that weren't skipped is known.
+    fn end_struct(&mut self) {
+        self.skipped_fields = 0;
+        self.exit();
+    }
 
-            slow(self, v);
+    fn write_u128(&mut self, v: u128) {
+        self.data.write_bits(v as u64, u64::BITS as usize);
+        self.data
+            .write_bits((v >> u64::BITS) as u64, u64::BITS as usize);
+    }
+
+    /// Writes the number of significant bits in `v` with [`Self::serialize_gamma`] followed by the
+    /// bits below the most significant 1 (which is implied).
+    fn serialize_varint(&mut self, v: u64) -> Result<()> {
+        let bits = (u64::BITS - v.leading_zeros()) as usize;
+        self.serialize_gamma(bits)?;
+        if bits > 1 {
+            self.data.write_bits(v ^ (1 << (bits - 1)), bits - 1);
         }
         Ok(())
     }
 
-    fn serialize_variant_index(&mut self, variant_index: u32) -> Result<()> {
-        self.serialize_len(variant_index as usize)
+    fn serialize_varint_u128(&mut self, v: u128) -> Result<()> {
+        let high = (v >> u64::BITS) as u64;
+        if high == 0 {
+            return self.serialize_varint(v as u64);
+        }
+
+        let high_bits = (u64::BITS - high.leading_zeros()) as usize;
+        self.serialize_gamma(u64::BITS as usize + high_bits)?;
+        self.data.write_bits(v as u64, u64::BITS as usize);
+        self.data
+            .write_bits(high ^ (1 << (high_bits - 1)), high_bits - 1);
+        Ok(())
     }
 }
 
 macro_rules! serialize_int {
-    ($name:ident, $a:ty, $b:ty) => {
+    ($name:ident, $a:ty, $b:ty, $tag:ident) => {
+        fn $name(self, v: $a) -> Result<Self::Ok> {
+            self.write_tag(Tag::$tag);
+            self.data.write_bits((v as $b).into(), <$b>::BITS as usize);
+            Ok(())
+        }
+    };
+}
+
+macro_rules! serialize_varint {
+    ($name:ident, $a:ty, $tag:ident) => {
         fn $name(self, v: $a) -> Result<Self::Ok> {
+            self.write_tag(Tag::$tag);
+            if self.config.varint {
+                return self.serialize_varint(v.into());
+            }
+            self.data.write_bits(v.into(), <$a>::BITS as usize);
+            Ok(())
+        }
+    };
+}
+
+macro_rules! serialize_zigzag {
+    ($name:ident, $a:ty, $b:ty, $tag:ident) => {
+        fn $name(self, v: $a) -> Result<Self::Ok> {
+            self.write_tag(Tag::$tag);
+            if self.config.varint {
+                // https://en.wikipedia.org/wiki/Variable-length_quantity#Zigzag_encoding
+                let zigzag = ((v << 1) ^ (v >> (<$a>::BITS - 1))) as $b;
+                return self.serialize_varint(zigzag.into());
+            }
             self.data.write_bits((v as $b).into(), <$b>::BITS as usize);
             Ok(())
         }
     };
 }
 
-impl<W: Write> Serializer for &mut BitcodeSerializer<W> {
+impl<'a, 'c, W: Write> Serializer for &'a mut BitcodeSerializer<'c, W> {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = Self;
+    type SerializeSeq = SerializeLen<'a, 'c, W>;
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
     type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
+    type SerializeMap = SerializeLen<'a, 'c, W>;
     type SerializeStruct = Self;
     type SerializeStructVariant = Self;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        self.write_tag(Tag::Bool);
         self.data.write_bit(v);
         Ok(())
     }
 
-    serialize_int!(serialize_i8, i8, u8);
-    serialize_int!(serialize_i16, i16, u16);
-    serialize_int!(serialize_i32, i32, u32);
-    serialize_int!(serialize_i64, i64, u64);
-    serialize_int!(serialize_u8, u8, u8);
-    serialize_int!(serialize_u16, u16, u16);
-    serialize_int!(serialize_u32, u32, u32);
-    serialize_int!(serialize_u64, u64, u64);
+    serialize_int!(serialize_i8, i8, u8, I8);
+    serialize_zigzag!(serialize_i16, i16, u16, I16);
+    serialize_zigzag!(serialize_i32, i32, u32, I32);
+    serialize_zigzag!(serialize_i64, i64, u64, I64);
+    serialize_int!(serialize_u8, u8, u8, U8);
+    serialize_varint!(serialize_u16, u16, U16);
+    serialize_varint!(serialize_u32, u32, U32);
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
+        if let Some(bits) = self.bits.take() {
+            if !fits(v, bits) {
+                return Err(E::Invalid("bits").e());
+            }
+            if bits != 0 {
+                self.data.write_bits(v, bits);
+            }
+            return Ok(());
+        }
+        self.write_tag(Tag::U64);
+        if self.config.varint {
+            return self.serialize_varint(v);
+        }
+        self.data.write_bits(v, u64::BITS as usize);
+        Ok(())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        self.write_tag(Tag::I128);
+        if self.config.varint {
+            return self.serialize_varint_u128(((v << 1) ^ (v >> (i128::BITS - 1))) as u128);
+        }
+        self.write_u128(v as u128);
+        Ok(())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        self.write_tag(Tag::U128);
+        if self.config.varint {
+            return self.serialize_varint_u128(v);
+        }
+        self.write_u128(v);
+        Ok(())
+    }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
+        self.write_tag(Tag::Char);
         let mut buf = [0; 4];
         let string = v.encode_utf8(&mut buf);
         self.data.write_bytes(string.as_bytes());
@@ -104,28 +443,32 @@ impl<W: Write> Serializer for &mut BitcodeSerializer<W> {
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
-        self.serialize_u32(v.to_bits())
+        self.write_tag(Tag::F32);
+        self.data.write_bits(v.to_bits().into(), u32::BITS as usize);
+        Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
-        self.serialize_u64(v.to_bits())
+        self.write_tag(Tag::F64);
+        self.data.write_bits(v.to_bits(), u64::BITS as usize);
+        Ok(())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        self.serialize_bytes(v.as_bytes())
+        self.write_tag(Tag::Str);
+        self.serialize_len_and_bytes(v.as_bytes())
     }
 
-    #[inline(never)] // Removing this makes bench_bitcode_serialize 7% slower.
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
-        if v.len() > isize::MAX as usize / u8::BITS as usize {
-            return Err(E::NotSupported("bytes.len() must be < isize::MAX / u8::BITS").e());
-        }
-        self.serialize_len(v.len())?;
-        self.data.write_bytes(v);
-        Ok(())
+        self.write_tag(Tag::Bytes);
+        self.serialize_len_and_bytes(v)
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
+        if self.config.self_describing {
+            Tag::None.write(&mut self.data);
+            return Ok(());
+        }
         self.serialize_bool(false)
     }
 
@@ -133,37 +476,50 @@ impl<W: Write> Serializer for &mut BitcodeSerializer<W> {
     where
         T: Serialize,
     {
-        self.serialize_bool(true)?;
-        value.serialize(self)
+        if self.config.self_describing {
+            Tag::Some.write(&mut self.data);
+        } else {
+            self.serialize_bool(true)?;
+        }
+        self.enter(0)?;
+        value.serialize(&mut *self)?;
+        self.exit();
+        Ok(())
     }
 
     fn serialize_unit(self) -> Result<Self::Ok> {
+        self.write_tag(Tag::Unit);
         Ok(())
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        self.write_tag(Tag::Unit);
         Ok(())
     }
 
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
     ) -> Result<Self::Ok> {
-        self.serialize_variant_index(variant_index)
+        self.serialize_variant(Tag::UnitVariant, name, variant_index)
     }
 
     fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<Self::Ok>
     where
         T: Serialize,
     {
-        value.serialize(self)
+        self.write_tag(Tag::NewtypeStruct);
+        self.enter(0)?;
+        value.serialize(&mut *self)?;
+        self.exit();
+        Ok(())
     }
 
     fn serialize_newtype_variant<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
         value: &T,
@@ -171,57 +527,83 @@ impl<W: Write> Serializer for &mut BitcodeSerializer<W> {
     where
         T: Serialize,
     {
-        self.serialize_variant_index(variant_index)?;
-        value.serialize(self)
+        self.serialize_variant(Tag::NewtypeVariant, name, variant_index)?;
+        self.enter(variant_slot(variant_index))?;
+        value.serialize(&mut *self)?;
+        self.exit();
+        Ok(())
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
-        let len = len.expect("sequence must have len");
-        self.serialize_len(len)?;
-        Ok(self)
+        self.write_tag(Tag::Seq);
+        SerializeLen::new(self, len)
     }
 
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.serialize_tuple_len(len)?;
+        self.enter(0)?;
         Ok(self)
     }
 
     fn serialize_tuple_struct(
         self,
-        _name: &'static str,
-        _len: usize,
+        name: &'static str,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
+        if bits_int_bits(name).is_some() {
+            // The len of a bits tuple struct is its number of bits.
+            if len > u64::BITS as usize {
+                return Err(E::NotSupported("bits > 64").e());
+            }
+            if self.config.self_describing {
+                Tag::Bits.write(&mut self.data);
+                self.data.write_bits(len as u64, BITS_LEN_BITS);
+            }
+            self.bits = Some(len);
+        } else {
+            self.serialize_tuple_len(len)?;
+        }
+        self.enter(0)?;
         Ok(self)
     }
 
     fn serialize_tuple_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.serialize_variant_index(variant_index)?;
+        self.serialize_variant(Tag::TupleVariant, name, variant_index)?;
+        if self.config.self_describing {
+            self.serialize_len(len)?;
+        }
+        self.enter(variant_slot(variant_index))?;
         Ok(self)
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
-        let len = len.expect("sequence must have len");
-        self.serialize_len(len)?;
-        Ok(self)
+        self.write_tag(Tag::Map);
+        SerializeLen::new(self, len)
     }
 
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.write_tag(Tag::Struct);
+        self.serialize_fields_len(len)?;
+        self.enter(0)?;
         Ok(self)
     }
 
     fn serialize_struct_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.serialize_variant_index(variant_index)?;
+        self.serialize_variant(Tag::StructVariant, name, variant_index)?;
+        self.serialize_fields_len(len)?;
+        self.enter(variant_slot(variant_index))?;
         Ok(self)
     }
 
@@ -235,92 +617,278 @@ macro_rules! ok_error_end {
         type Ok = ();
         type Error = Error;
         fn end(self) -> Result<Self::Ok> {
+            self.exit();
             Ok(())
         }
     };
 }
 
-impl<W: Write> SerializeSeq for &mut BitcodeSerializer<W> {
-    ok_error_end!();
+/// Serializes the elements of a sequence or map. If the length isn't known up front, the elements
+/// are buffered until [`SerializeSeq::end`] since their count has to be written before them.
+pub struct SerializeLen<'a, 'c, W> {
+    serializer: &'a mut BitcodeSerializer<'c, W>,
+    items: Items<'c>,
+}
+
+/// Where [`SerializeLen`] serializes the elements.
+enum Items<'c> {
+    /// Right after the length.
+    Direct,
+    /// Into a buffer until the length is known.
+    UnknownLen(UnknownLen<'c>),
+    /// Right after a spot for the length reserved with [`Write::defer_len`] in the buffer of an
+    /// enclosing sequence of unknown length.
+    DeferredLen { index: usize, len: usize },
+    /// Into columns that are written after the length by [`SerializeLen::end_len`].
+    Columns(BitcodeSerializer<'c, ColumnsWrite>),
+}
+
+struct UnknownLen<'c> {
+    serializer: BitcodeSerializer<'c, LenBuffer>,
+    len: usize,
+}
+
+impl<'a, 'c, W: Write> SerializeLen<'a, 'c, W> {
+    fn new(serializer: &'a mut BitcodeSerializer<'c, W>, len: Option<usize>) -> Result<Self> {
+        serializer.enter(0)?;
+        let items = if let Some(len) = len {
+            serializer.serialize_len(len)?;
+            if serializer.config.columnar && !serializer.in_columns {
+                Items::Columns(BitcodeSerializer {
+                    data: Default::default(),
+                    config: serializer.config,
+                    depth_budget: serializer.depth_budget,
+                    in_columns: true,
+                    bits: None,
+                    skipped_fields: 0,
+                })
+            } else {
+                Items::Direct
+            }
+        } else if serializer.config.aligned_strings {
+            // Alignment depends on the number of bits taken by the length, which isn't known yet.
+            return Err(E::NotSupported("unknown len with aligned strings").e());
+        } else if serializer.config.columnar {
+            // Buffered elements would all end up in the column of the sequence.
+            return Err(E::NotSupported("unknown len with columnar layout").e());
+        } else if let Some(index) = serializer.data.defer_len() {
+            Items::DeferredLen { index, len: 0 }
+        } else {
+            Items::UnknownLen(UnknownLen {
+                serializer: BitcodeSerializer {
+                    data: Default::default(),
+                    config: serializer.config,
+                    depth_budget: serializer.depth_budget,
+                    in_columns: false,
+                    bits: None,
+                    skipped_fields: 0,
+                },
+                len: 0,
+            })
+        };
+        Ok(Self { serializer, items })
+    }
+
+    fn serialize_item<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.serializer.data.check()?;
+        match &mut self.items {
+            Items::Direct | Items::DeferredLen { .. } => value.serialize(&mut *self.serializer),
+            Items::UnknownLen(buffer) => value.serialize(&mut buffer.serializer),
+            Items::Columns(serializer) => value.serialize(serializer),
+        }
+    }
+
+    /// Serializes the key (`slot` 0) or the value (`slot` 1) of a map entry. Keys and values have
+    /// their own paths (see [`Write::enter_path`]) so the fields of one can't share columns with
+    /// the other.
+    fn serialize_map_item<T>(&mut self, value: &T, slot: u64) -> Result<()>
+    where
+        T: Serialize + ?Sized,
+    {
+        match &mut self.items {
+            Items::Direct | Items::DeferredLen { .. } => self.serializer.data.enter_path(slot),
+            Items::UnknownLen(buffer) => buffer.serializer.data.enter_path(slot),
+            Items::Columns(serializer) => serializer.data.enter_path(slot),
+        }
+        self.serialize_item(value)?;
+        match &mut self.items {
+            Items::Direct | Items::DeferredLen { .. } => self.serializer.data.exit_path(),
+            Items::UnknownLen(buffer) => buffer.serializer.data.exit_path(),
+            Items::Columns(serializer) => serializer.data.exit_path(),
+        }
+        Ok(())
+    }
+
+    /// Counts an element (or a map entry) of a sequence with an unknown length.
+    fn count_item(&mut self) -> Result<()> {
+        if let Items::UnknownLen(UnknownLen { len, .. }) | Items::DeferredLen { len, .. } =
+            &mut self.items
+        {
+            *len = len
+                .checked_add(1)
+                .ok_or(E::NotSupported("len must be < usize::MAX").e())?;
+        }
+        Ok(())
+    }
+
+    fn end_len(self) -> Result<()> {
+        match self.items {
+            Items::Direct => (),
+            Items::UnknownLen(buffer) => {
+                self.serializer.serialize_len(buffer.len)?;
+                // Insert the lengths of the nested sequences while copying the elements.
+                let (data, lens) = buffer.serializer.data.parts();
+                let mut start = 0;
+                for &(position, len) in lens {
+                    data.write_range_to(start..position, &mut self.serializer.data);
+                    self.serializer.serialize_len(len)?;
+                    start = position;
+                }
+                data.write_range_to(start..data.bits_written(), &mut self.serializer.data);
+            }
+            Items::DeferredLen { index, len } => self.serializer.data.set_deferred_len(index, len),
+            Items::Columns(columns) => {
+                // The bit lengths of the columns come first so they can be read without knowing
+                // the type of the elements.
+                let columns = columns.data.columns();
+                self.serializer.serialize_len(columns.len())?;
+                for column in columns {
+                    self.serializer.serialize_len(column.bits_written())?;
+                }
+                for column in columns {
+                    column.write_to(&mut self.serializer.data);
+                }
+            }
+        }
+        self.serializer.exit();
+        Ok(())
+    }
+}
+
+impl<W: Write> SerializeSeq for SerializeLen<'_, '_, W> {
+    type Ok = ();
+    type Error = Error;
+
     fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        self.count_item()?;
+        self.serialize_item(value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        self.end_len()
     }
 }
 
-impl<W: Write> SerializeTuple for &mut BitcodeSerializer<W> {
+impl<W: Write> SerializeTuple for &mut BitcodeSerializer<'_, W> {
     ok_error_end!();
     fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        value.serialize(&mut **self)?;
+        self.data.next_path();
+        Ok(())
     }
 }
 
-impl<W: Write> SerializeTupleStruct for &mut BitcodeSerializer<W> {
+impl<W: Write> SerializeTupleStruct for &mut BitcodeSerializer<'_, W> {
     ok_error_end!();
     fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        value.serialize(&mut **self)?;
+        self.data.next_path();
+        Ok(())
     }
 }
 
-impl<W: Write> SerializeTupleVariant for &mut BitcodeSerializer<W> {
+impl<W: Write> SerializeTupleVariant for &mut BitcodeSerializer<'_, W> {
     ok_error_end!();
     fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        value.serialize(&mut **self)?;
+        self.data.next_path();
+        Ok(())
     }
 }
 
-impl<W: Write> SerializeMap for &mut BitcodeSerializer<W> {
-    ok_error_end!();
+impl<W: Write> SerializeMap for SerializeLen<'_, '_, W> {
+    type Ok = ();
+    type Error = Error;
+
     fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<()>
     where
         T: Serialize,
     {
-        key.serialize(&mut **self)
+        self.count_item()?;
+        self.serialize_map_item(key, 0)
     }
 
     fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        self.serialize_map_item(value, 1)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        self.end_len()
     }
 }
 
-impl<W: Write> SerializeStruct for &mut BitcodeSerializer<W> {
-    ok_error_end!();
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+impl<W: Write> SerializeStruct for &mut BitcodeSerializer<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        self.serialize_field_prefix(key)?;
+        value.serialize(&mut **self)?;
+        self.data.next_path();
+        Ok(())
     }
 
     fn skip_field(&mut self, _key: &'static str) -> Result<()> {
-        Err(E::NotSupported("skip_field").e())
+        self.skip_struct_field()
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        self.end_struct();
+        Ok(())
     }
 }
 
-impl<W: Write> SerializeStructVariant for &mut BitcodeSerializer<W> {
-    ok_error_end!();
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+impl<W: Write> SerializeStructVariant for &mut BitcodeSerializer<'_, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: Serialize,
     {
-        value.serialize(&mut **self)
+        self.serialize_field_prefix(key)?;
+        value.serialize(&mut **self)?;
+        self.data.next_path();
+        Ok(())
     }
 
     fn skip_field(&mut self, _key: &'static str) -> Result<()> {
-        Err(E::NotSupported("skip_field").e())
+        self.skip_struct_field()
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        self.end_struct();
+        Ok(())
     }
 }
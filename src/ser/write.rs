@@ -1,4 +1,7 @@
 use crate::nightly::div_ceil;
+use crate::{Result, E};
+use std::collections::HashMap;
+use std::ops::Range;
 
 type Word = u64;
 const WORD_BITS: usize = Word::BITS as usize;
@@ -11,11 +14,103 @@ pub trait Write {
     fn write_bit(&mut self, v: bool);
     /// Writes bytes. `bytes.len()`must be < `isize::MAX as usize / u8::BITS as usize`.
     fn write_bytes(&mut self, bytes: &[u8]);
+    /// Writes zeros up to the next byte boundary.
+    fn align_to_byte(&mut self);
+
+    // Hooks for the columnar layout (see [`ColumnsWrite`]). The path of a value is made of the
+    // slots of the values it's nested in, e.g. the fields of structs.
+
+    /// Enters a value nested in the current one at `slot`.
+    #[inline(always)]
+    fn enter_path(&mut self, _slot: u64) {}
+    /// Moves on to the next slot, e.g. the next field of a struct.
+    #[inline(always)]
+    fn next_path(&mut self) {}
+    /// Exits the value entered by [`Write::enter_path`].
+    #[inline(always)]
+    fn exit_path(&mut self) {}
+
+    // Hooks for sequences of unknown length nested in another one (see [`LenBuffer`]).
+
+    /// Marks where the length of a sequence of unknown length goes and returns an index for
+    /// [`Write::set_deferred_len`] or [`None`] if lengths can't be deferred.
+    fn defer_len(&mut self) -> Option<usize> {
+        None
+    }
+    /// Sets the length marked by [`Write::defer_len`].
+    fn set_deferred_len(&mut self, _index: usize, _len: usize) {}
+
+    /// Returns an error if writing has already failed (e.g. the slice passed to
+    /// [`crate::serialize_into_slice`] is full), so serializing can stop early instead of failing at
+    /// the end.
+    #[inline(always)]
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for &mut W {
+    #[inline(always)]
+    fn write_bits(&mut self, word: Word, bits: usize) {
+        (**self).write_bits(word, bits)
+    }
+
+    #[inline(always)]
+    fn write_bit(&mut self, v: bool) {
+        (**self).write_bit(v)
+    }
+
+    #[inline(always)]
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        (**self).write_bytes(bytes)
+    }
+
+    fn align_to_byte(&mut self) {
+        (**self).align_to_byte()
+    }
+
+    #[inline(always)]
+    fn enter_path(&mut self, slot: u64) {
+        (**self).enter_path(slot)
+    }
+
+    #[inline(always)]
+    fn next_path(&mut self) {
+        (**self).next_path()
+    }
+
+    #[inline(always)]
+    fn exit_path(&mut self) {
+        (**self).exit_path()
+    }
+
+    fn defer_len(&mut self) -> Option<usize> {
+        (**self).defer_len()
+    }
+
+    fn set_deferred_len(&mut self, index: usize, len: usize) {
+        (**self).set_deferred_len(index, len)
+    }
+
+    #[inline(always)]
+    fn check(&self) -> Result<()> {
+        (**self).check()
+    }
 }
 
 pub trait WriteWith: Write + Default {
     fn clear(&mut self);
     fn into_inner(self) -> Vec<u8>;
+    /// Like [`WriteWith::into_inner`] but borrows the bytes instead of copying them.
+    fn as_slice(&mut self) -> &[u8];
+    /// Writes all the bits written so far to another [`Write`].
+    fn write_to(&self, w: &mut impl Write) {
+        self.write_range_to(0..self.bits_written(), w)
+    }
+    /// Writes the bits in `range` to another [`Write`].
+    fn write_range_to(&self, range: Range<usize>, w: &mut impl Write);
+    /// Returns how many bits have been written so far.
+    fn bits_written(&self) -> usize;
 }
 
 #[cfg(target_endian = "little")]
@@ -46,6 +141,11 @@ mod bitvec_write {
         fn write_bytes(&mut self, bytes: &[u8]) {
             self.extend_from_bitslice(&BitSlice::<u8, Lsb0>::from_slice(bytes));
         }
+
+        fn align_to_byte(&mut self) {
+            let len = self.len();
+            self.resize(div_ceil(len, u8::BITS as usize) * u8::BITS as usize, false);
+        }
     }
 
     impl WriteWith for BitVecImpl {
@@ -57,6 +157,22 @@ mod bitvec_write {
             self.force_align();
             self.into_vec()
         }
+
+        fn as_slice(&mut self) -> &[u8] {
+            // Bits past the end might be left over from before clear was called.
+            self.set_uninitialized(false);
+            self.as_raw_slice()
+        }
+
+        fn write_range_to(&self, range: Range<usize>, w: &mut impl Write) {
+            for chunk in self[range].chunks(WORD_BITS) {
+                w.write_bits(chunk.load_le(), chunk.len());
+            }
+        }
+
+        fn bits_written(&self) -> usize {
+            self.len()
+        }
     }
 }
 
@@ -222,6 +338,11 @@ impl Write for SerVec {
             write_many_bytes(self, bytes)
         }
     }
+
+    fn align_to_byte(&mut self) {
+        // Bits past len are always zero.
+        self.len = div_ceil(self.len, u8::BITS as usize) * u8::BITS as usize;
+    }
 }
 
 fn read_0_to_7_bytes_into_word(mut bytes: &[u8]) -> Word {
@@ -264,6 +385,549 @@ impl WriteWith for SerVec {
         bytes.truncate(div_ceil(self.len, u8::BITS as usize));
         bytes
     }
+
+    fn as_slice(&mut self) -> &[u8] {
+        // Words are little endian since SerVec is only used on little endian targets.
+        let bytes: &[u8] = bytemuck::cast_slice(&self.words);
+        &bytes[..div_ceil(self.len, u8::BITS as usize)]
+    }
+
+    fn write_range_to(&self, range: Range<usize>, w: &mut impl Write) {
+        debug_assert!(range.end <= self.len);
+        let shift = range.start % WORD_BITS;
+        let mut index = range.start / WORD_BITS;
+        let mut bits = range.len();
+        while bits != 0 {
+            let mut word = self.words[index] >> shift;
+            if shift != 0 {
+                word |= self
+                    .words
+                    .get(index + 1)
+                    .map_or(0, |&w| w << (WORD_BITS - shift));
+            }
+            let n = bits.min(WORD_BITS);
+            if n != WORD_BITS {
+                word &= (1 << n) - 1;
+            }
+            w.write_bits(word, n);
+            index += 1;
+            bits -= n;
+        }
+    }
+
+    fn bits_written(&self) -> usize {
+        self.len
+    }
+}
+
+/// A [`Write`] that buffers a sequence of unknown length until its length is known. The lengths
+/// of the sequences of unknown length nested in it are recorded instead of buffered again, so
+/// their elements are copied once rather than once per level of nesting.
+#[derive(Default)]
+pub struct LenBuffer {
+    data: WriteWithImpl,
+    /// The bit positions and lengths of the nested sequences in the order they start.
+    lens: Vec<(usize, usize)>,
+}
+
+impl LenBuffer {
+    /// Returns the buffered bits and the bit positions and lengths of the nested sequences.
+    pub fn parts(&self) -> (&WriteWithImpl, &[(usize, usize)]) {
+        (&self.data, &self.lens)
+    }
+}
+
+impl Write for LenBuffer {
+    #[inline(always)]
+    fn write_bits(&mut self, word: Word, bits: usize) {
+        self.data.write_bits(word, bits)
+    }
+
+    #[inline(always)]
+    fn write_bit(&mut self, v: bool) {
+        self.data.write_bit(v)
+    }
+
+    #[inline(always)]
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.write_bytes(bytes)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.data.align_to_byte()
+    }
+
+    fn defer_len(&mut self) -> Option<usize> {
+        self.lens.push((self.data.bits_written(), 0));
+        Some(self.lens.len() - 1)
+    }
+
+    fn set_deferred_len(&mut self, index: usize, len: usize) {
+        self.lens[index].1 = len;
+    }
+}
+
+/// Numbers paths (see [`Write::enter_path`]) in the order they're first entered. Entering,
+/// moving between and exiting paths only looks up the number of the next path from the current
+/// one, so paths aren't allocated or hashed as a whole.
+#[derive(Default)]
+pub struct Paths {
+    /// The number of each path entered so far by the number of the path it's nested in and its
+    /// last slot. The empty path is 0.
+    numbers: HashMap<(usize, u64), usize>,
+    /// The last slot and the number of each path that is currently entered.
+    stack: Vec<(u64, usize)>,
+}
+
+impl Paths {
+    /// Returns the number of the current path.
+    pub fn current(&self) -> usize {
+        self.stack.last().map_or(0, |&(_, number)| number)
+    }
+
+    /// Enters `slot` and returns the number of the new path.
+    pub fn enter(&mut self, slot: u64) -> usize {
+        let parent = self.current();
+        let next = self.numbers.len() + 1;
+        let number = *self.numbers.entry((parent, slot)).or_insert(next);
+        self.stack.push((slot, number));
+        number
+    }
+
+    /// Moves on to the next slot and returns the number of the new path.
+    pub fn next(&mut self) -> usize {
+        let (slot, _) = self.stack.pop().unwrap();
+        self.enter(slot + 1)
+    }
+
+    /// Exits the last slot and returns the number of the new path.
+    pub fn exit(&mut self) -> usize {
+        self.stack.pop();
+        self.current()
+    }
+}
+
+/// A [`Write`] that writes each path (see [`Write::enter_path`]) to its own column, so that e.g.
+/// the same field of every element of a sequence ends up in the same column. Columns are numbered
+/// in the order their paths are first entered.
+pub struct ColumnsWrite {
+    columns: Vec<WriteWithImpl>,
+    paths: Paths,
+    current: usize,
+}
+
+impl Default for ColumnsWrite {
+    fn default() -> Self {
+        let mut me = Self {
+            columns: vec![],
+            paths: Default::default(),
+            current: 0,
+        };
+        me.select_column(0);
+        me
+    }
+}
+
+impl ColumnsWrite {
+    /// Selects the column of path `number`, creating it if the path is new.
+    fn select_column(&mut self, number: usize) {
+        if number == self.columns.len() {
+            self.columns.push(Default::default());
+        }
+        self.current = number;
+    }
+
+    fn column(&mut self) -> &mut WriteWithImpl {
+        &mut self.columns[self.current]
+    }
+
+    /// Returns the columns in the order they were created.
+    pub fn columns(&self) -> &[WriteWithImpl] {
+        &self.columns
+    }
+}
+
+impl Write for ColumnsWrite {
+    fn write_bits(&mut self, word: Word, bits: usize) {
+        self.column().write_bits(word, bits)
+    }
+
+    fn write_bit(&mut self, v: bool) {
+        self.column().write_bit(v)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.column().write_bytes(bytes)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.column().align_to_byte()
+    }
+
+    fn enter_path(&mut self, slot: u64) {
+        let number = self.paths.enter(slot);
+        self.select_column(number);
+    }
+
+    fn next_path(&mut self) {
+        let number = self.paths.next();
+        self.select_column(number);
+    }
+
+    fn exit_path(&mut self) {
+        let number = self.paths.exit();
+        self.select_column(number);
+    }
+}
+
+/// A [`Write`] that only counts the bits written to it.
+#[derive(Debug, Default)]
+pub struct CountWrite {
+    pub bits: usize,
+}
+
+impl Write for CountWrite {
+    #[inline(always)]
+    fn write_bits(&mut self, _: Word, bits: usize) {
+        self.bits += bits;
+    }
+
+    #[inline(always)]
+    fn write_bit(&mut self, _: bool) {
+        self.bits += 1;
+    }
+
+    #[inline(always)]
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bits += bytes.len() * u8::BITS as usize;
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bits = div_ceil(self.bits, u8::BITS as usize) * u8::BITS as usize;
+    }
+}
+
+/// Collects bits into whole words for [`Write`]s that output one word at a time.
+#[derive(Default)]
+struct PartialWord {
+    word: Word,
+    bits: usize,
+}
+
+impl PartialWord {
+    /// Like [`Write::write_bits`] but returns the word if it's now whole.
+    #[inline(always)]
+    fn write_bits(&mut self, word: Word, bits: usize) -> Option<Word> {
+        debug_assert!(bits <= WORD_BITS);
+        self.word |= word << self.bits;
+
+        let bits_after = self.bits + bits;
+        if bits_after >= WORD_BITS {
+            let whole = self.word;
+            self.word = word
+                .checked_shr((WORD_BITS - self.bits) as u32)
+                .unwrap_or(0);
+            self.bits = bits_after - WORD_BITS;
+            Some(whole)
+        } else {
+            self.bits = bits_after;
+            None
+        }
+    }
+
+    /// Like [`Write::write_bytes`] but passes whole words to `f`.
+    fn write_bytes(&mut self, bytes: &[u8], mut f: impl FnMut(Word)) {
+        let chunks = bytes.chunks_exact(WORD_BITS / u8::BITS as usize);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            if let Some(whole) =
+                self.write_bits(Word::from_le_bytes(chunk.try_into().unwrap()), WORD_BITS)
+            {
+                f(whole);
+            }
+        }
+        if let Some(whole) = self.write_bits(
+            read_0_to_7_bytes_into_word(remainder),
+            remainder.len() * u8::BITS as usize,
+        ) {
+            f(whole);
+        }
+    }
+
+    /// Like [`Write::align_to_byte`] but returns the word if it's now whole.
+    fn align_to_byte(&mut self) -> Option<Word> {
+        // Bits past `bits` are always zero.
+        let bits = div_ceil(self.bits, u8::BITS as usize) * u8::BITS as usize;
+        if bits == WORD_BITS {
+            self.bits = 0;
+            Some(std::mem::take(&mut self.word))
+        } else {
+            self.bits = bits;
+            None
+        }
+    }
+
+    /// Returns the remaining bits as bytes and how many of the bytes are used.
+    fn remainder(&self) -> ([u8; 8], usize) {
+        (
+            self.word.to_le_bytes(),
+            div_ceil(self.bits, u8::BITS as usize),
+        )
+    }
+}
+
+/// A [`Write`] that passes whole words on to an [`std::io::Write`] instead of keeping all of
+/// them in memory. Errors are stored and returned by [`IoWrite::finish`].
+pub struct IoWrite<W> {
+    inner: W,
+    /// Bytes of whole words that haven't been written to `inner` yet.
+    buffer: Vec<u8>,
+    partial: PartialWord,
+    /// Bytes written to `inner` so far.
+    written: usize,
+    limit: Option<usize>,
+    error: Option<E>,
+}
+
+impl<W: std::io::Write> IoWrite<W> {
+    /// How many bytes are buffered before writing them to `inner`.
+    const BUFFER_BYTES: usize = 4096;
+
+    /// Creates an [`IoWrite`] that stops writing and returns an error once more than `limit`
+    /// bytes would be written.
+    pub fn new(inner: W, limit: Option<usize>) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(Self::BUFFER_BYTES),
+            partial: Default::default(),
+            written: 0,
+            limit,
+            error: None,
+        }
+    }
+
+    fn push_word(&mut self, word: Word) {
+        self.buffer.extend_from_slice(&word.to_le_bytes());
+        if self.buffer.len() >= Self::BUFFER_BYTES {
+            self.flush_buffer();
+        }
+    }
+
+    fn flush_buffer(&mut self) {
+        if self.error.is_none() {
+            self.written += self.buffer.len();
+            if self.limit.is_some_and(|limit| self.written > limit) {
+                self.error = Some(E::LimitExceeded("size"));
+            } else if let Err(e) = self.inner.write_all(&self.buffer) {
+                self.error = Some(E::Io(e.kind()));
+            }
+        }
+        self.buffer.clear();
+    }
+
+    /// Writes the remaining bits (padded to a byte) and flushes `inner`.
+    pub fn finish(mut self) -> Result<W> {
+        let (bytes, len) = self.partial.remainder();
+        self.buffer.extend_from_slice(&bytes[..len]);
+        self.flush_buffer();
+
+        if let Some(e) = self.error {
+            return Err(e.e());
+        }
+        self.inner.flush().map_err(|e| E::Io(e.kind()).e())?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: std::io::Write> Write for IoWrite<W> {
+    fn write_bits(&mut self, word: Word, bits: usize) {
+        if let Some(whole) = self.partial.write_bits(word, bits) {
+            self.push_word(whole);
+        }
+    }
+
+    fn write_bit(&mut self, v: bool) {
+        self.write_bits(v as Word, 1);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut partial = std::mem::take(&mut self.partial);
+        partial.write_bytes(bytes, |whole| self.push_word(whole));
+        self.partial = partial;
+    }
+
+    fn align_to_byte(&mut self) {
+        if let Some(whole) = self.partial.align_to_byte() {
+            self.push_word(whole);
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if let Some(e) = &self.error {
+            return Err(e.clone().e());
+        }
+        // Bytes that haven't been flushed yet count too, so the limit is noticed right away.
+        if self
+            .limit
+            .is_some_and(|limit| self.written + self.buffer.len() > limit)
+        {
+            return Err(E::LimitExceeded("size").e());
+        }
+        Ok(())
+    }
+}
+
+/// A [`Write`] into a fixed size [`&mut [u8]`][`prim@slice`] that never allocates. Running out
+/// of space is returned by [`Write::check`] and [`SliceWrite::finish`].
+pub struct SliceWrite<'a> {
+    slice: &'a mut [u8],
+    /// Bytes of `slice` written so far.
+    written: usize,
+    partial: PartialWord,
+    full: bool,
+}
+
+impl<'a> SliceWrite<'a> {
+    /// Creates a [`SliceWrite`] that writes to the start of `slice`.
+    pub fn new(slice: &'a mut [u8]) -> Self {
+        Self {
+            slice,
+            written: 0,
+            partial: Default::default(),
+            full: false,
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        match self.slice.get_mut(self.written..self.written + bytes.len()) {
+            Some(dst) if !self.full => {
+                dst.copy_from_slice(bytes);
+                self.written += bytes.len();
+            }
+            _ => self.full = true,
+        }
+    }
+
+    fn push_word(&mut self, word: Word) {
+        self.push_bytes(&word.to_le_bytes());
+    }
+
+    /// Writes the remaining bits (padded to a byte) and returns how many bytes were written.
+    pub fn finish(mut self) -> Result<usize> {
+        let (bytes, len) = self.partial.remainder();
+        self.push_bytes(&bytes[..len]);
+        self.check()?;
+        Ok(self.written)
+    }
+}
+
+impl Write for SliceWrite<'_> {
+    fn write_bits(&mut self, word: Word, bits: usize) {
+        if let Some(whole) = self.partial.write_bits(word, bits) {
+            self.push_word(whole);
+        }
+    }
+
+    fn write_bit(&mut self, v: bool) {
+        self.write_bits(v as Word, 1);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut partial = std::mem::take(&mut self.partial);
+        partial.write_bytes(bytes, |whole| self.push_word(whole));
+        self.partial = partial;
+    }
+
+    fn align_to_byte(&mut self) {
+        if let Some(whole) = self.partial.align_to_byte() {
+            self.push_word(whole);
+        }
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.full {
+            Err(E::BufferFull.e())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A [`Write`] that appends to a [`WriteWith`] and stops serializing with an error once more
+/// than `limit` bytes are written after the bits that were already there.
+pub struct LimitWrite<'a, W> {
+    inner: &'a mut W,
+    /// Bits of `inner` written before this value plus `limit` bytes.
+    end: usize,
+}
+
+impl<'a, W: WriteWith> LimitWrite<'a, W> {
+    /// Creates a [`LimitWrite`] that appends to `inner`, which has no limit if `limit` is [`None`].
+    pub fn new(inner: &'a mut W, limit: Option<usize>) -> Self {
+        let end = limit.map_or(usize::MAX, |limit| {
+            inner
+                .bits_written()
+                .saturating_add(limit.saturating_mul(u8::BITS as usize))
+        });
+        Self { inner, end }
+    }
+
+    /// Returns an error if more than `limit` bytes were written.
+    pub fn finish(self) -> Result<()> {
+        self.check()
+    }
+}
+
+impl<W: WriteWith> Write for LimitWrite<'_, W> {
+    #[inline(always)]
+    fn write_bits(&mut self, word: Word, bits: usize) {
+        self.inner.write_bits(word, bits)
+    }
+
+    #[inline(always)]
+    fn write_bit(&mut self, v: bool) {
+        self.inner.write_bit(v)
+    }
+
+    #[inline(always)]
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.inner.write_bytes(bytes)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.inner.align_to_byte()
+    }
+
+    #[inline(always)]
+    fn enter_path(&mut self, slot: u64) {
+        self.inner.enter_path(slot)
+    }
+
+    #[inline(always)]
+    fn next_path(&mut self) {
+        self.inner.next_path()
+    }
+
+    #[inline(always)]
+    fn exit_path(&mut self) {
+        self.inner.exit_path()
+    }
+
+    fn defer_len(&mut self) -> Option<usize> {
+        self.inner.defer_len()
+    }
+
+    fn set_deferred_len(&mut self, index: usize, len: usize) {
+        self.inner.set_deferred_len(index, len)
+    }
+
+    fn check(&self) -> Result<()> {
+        self.inner.check()?;
+        if self.inner.bits_written() > self.end {
+            return Err(E::LimitExceeded("size").e());
+        }
+        Ok(())
+    }
 }
 
 #[cfg(all(test, not(miri)))]
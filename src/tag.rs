@@ -0,0 +1,101 @@
+use crate::de::read::Read;
+use crate::ser::write::Write;
+use crate::{Result, E};
+
+/// The type of a value in the self-describing encoding (see
+/// [`Config::with_self_describing_encoding`][`crate::Config::with_self_describing_encoding`]).
+/// Written with [`TAG_BITS`] bits before the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Tag {
+    /// Units and unit structs.
+    Unit,
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    F32,
+    F64,
+    Char,
+    Str,
+    Bytes,
+    None,
+    Some,
+    NewtypeStruct,
+    Seq,
+    /// Tuples and tuple structs. Followed by their number of fields.
+    Tuple,
+    /// Followed by the number of fields, each of which is preceded by its name.
+    Struct,
+    Map,
+    /// Followed by the gamma encoded variant index.
+    UnitVariant,
+    /// Followed by the gamma encoded variant index.
+    NewtypeVariant,
+    /// Followed by the gamma encoded variant index and the number of fields.
+    TupleVariant,
+    /// Followed by the gamma encoded variant index and the number of fields, each of which is
+    /// preceded by its name.
+    StructVariant,
+    /// A [`bits`][`crate::bits`] integer. Followed by its number of bits written with
+    /// [`BITS_LEN_BITS`] bits.
+    Bits,
+}
+
+/// The number of bits of a [`Tag`].
+pub(crate) const TAG_BITS: usize = 5;
+
+/// The number of bits of the number of bits of a [`Tag::Bits`] integer.
+pub(crate) const BITS_LEN_BITS: usize = 7;
+
+impl Tag {
+    const ALL: [Self; 29] = [
+        Self::Unit,
+        Self::Bool,
+        Self::I8,
+        Self::I16,
+        Self::I32,
+        Self::I64,
+        Self::I128,
+        Self::U8,
+        Self::U16,
+        Self::U32,
+        Self::U64,
+        Self::U128,
+        Self::F32,
+        Self::F64,
+        Self::Char,
+        Self::Str,
+        Self::Bytes,
+        Self::None,
+        Self::Some,
+        Self::NewtypeStruct,
+        Self::Seq,
+        Self::Tuple,
+        Self::Struct,
+        Self::Map,
+        Self::UnitVariant,
+        Self::NewtypeVariant,
+        Self::TupleVariant,
+        Self::StructVariant,
+        Self::Bits,
+    ];
+
+    pub(crate) fn write(self, w: &mut impl Write) {
+        w.write_bits(self as u64, TAG_BITS);
+    }
+
+    pub(crate) fn read<'a>(r: &mut impl Read<'a>) -> Result<Self> {
+        let tag = r.read_bits(TAG_BITS)?;
+        Self::ALL
+            .get(tag as usize)
+            .copied()
+            .ok_or(E::Invalid("tag").e())
+    }
+}
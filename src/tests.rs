@@ -1,8 +1,11 @@
-use crate::de::deserialize_with;
-use crate::de::read::{BitSliceImpl, DeVec};
-use crate::ser::serialize_with;
-use crate::ser::write::{BitVecImpl, SerVec};
-use crate::{deserialize, E};
+use crate::de::read::{BitSliceImpl, DeVec, ReadWith};
+use crate::de::{deserialize_prefix_with, deserialize_with};
+use crate::ser::write::{BitVecImpl, SerVec, WriteWith};
+use crate::ser::{serialize_in, serialize_into, serialize_with};
+use crate::{
+    deserialize, Config, Decoder, Encoder, Error, ErrorKind, Packer, Progress, PushDecoder,
+    Unpacker, E, FORMAT_VERSION,
+};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -10,47 +13,115 @@ use std::fmt::Debug;
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen_test::*;
 
-fn the_same_inner<T: Clone + Debug + PartialEq + Serialize + DeserializeOwned>(t: T) {
+fn the_same_config<T: Debug + PartialEq + Serialize + DeserializeOwned>(t: &T, config: &Config) {
     let serialized = {
-        let a = serialize_with::<BitVecImpl>(&t).unwrap();
+        let a = serialize_with::<BitVecImpl>(t, config).unwrap();
         #[cfg(target_endian = "little")]
         {
             // SerVec doesn't work on big endian.
-            let b = serialize_with::<SerVec>(&t).unwrap();
+            let b = serialize_with::<SerVec>(t, config).unwrap();
             assert_eq!(a, b);
         }
+        let mut c = vec![];
+        config.serialize_into_writer(&mut c, t).unwrap();
+        assert_eq!(a, c);
+
+        let bits = serialize_into(t, BitVecImpl::default(), config)
+            .unwrap()
+            .len();
+        assert_eq!(config.serialized_size_bits(t), Ok(bits));
+
+        let mut d = vec![0; a.len()];
+        assert_eq!(config.serialize_into_slice(t, &mut d), Ok(a.len()));
+        assert_eq!(a, d);
+        if let Some(len) = a.len().checked_sub(1) {
+            assert_eq!(
+                config.serialize_into_slice(t, &mut d[..len]),
+                Err(E::BufferFull.e())
+            );
+        }
         a
     };
 
-    let a: T = deserialize_with::<T, BitSliceImpl>(&serialized).expect("BitSliceImpl error");
+    let a: T =
+        deserialize_with::<T, BitSliceImpl>(&serialized, config).expect("BitSliceImpl error");
 
-    assert_eq!(t, a);
+    assert_eq!(t, &a);
 
     #[cfg(target_endian = "little")]
     {
         // DeVec does not work on big endian.
-        let b: T = deserialize_with::<T, DeVec>(&serialized).expect("DeVec error");
-        assert_eq!(t, b);
+        let b: T = deserialize_with::<T, DeVec>(&serialized, config).expect("DeVec error");
+        assert_eq!(t, &b);
         assert_eq!(a, b);
     }
 
+    let c: T = config
+        .deserialize_from_reader(serialized.as_slice())
+        .expect("IoRead error");
+    assert_eq!(t, &c);
+
+    let mut prefixed = serialized.clone();
+    prefixed.extend_from_slice(&[1, 2, 3]);
+    let (d, rest) = config
+        .deserialize_prefix::<T>(&prefixed)
+        .expect("prefix error");
+    assert_eq!(t, &d);
+    assert_eq!(rest, &[1, 2, 3]);
+    let (e, rest) = deserialize_prefix_with::<T, BitSliceImpl>(&prefixed, config)
+        .expect("BitSliceImpl prefix error");
+    assert_eq!(t, &e);
+    assert_eq!(rest, &[1, 2, 3]);
+
     let mut bytes = serialized.clone();
     bytes.push(0);
     #[cfg(target_endian = "little")]
     assert_eq!(
-        deserialize_with::<T, DeVec>(&bytes),
+        deserialize_with::<T, DeVec>(&bytes, config),
         Err(E::ExpectedEof.e())
     );
     assert_eq!(
-        deserialize_with::<T, BitSliceImpl>(&bytes),
+        deserialize_with::<T, BitSliceImpl>(&bytes, config),
+        Err(E::ExpectedEof.e())
+    );
+    assert_eq!(
+        config.deserialize_from_reader::<T>(bytes.as_slice()),
         Err(E::ExpectedEof.e())
     );
 
     let mut bytes = serialized.clone();
     if bytes.pop().is_some() {
         #[cfg(target_endian = "little")]
-        assert_eq!(deserialize_with::<T, DeVec>(&bytes), Err(E::Eof.e()));
-        assert_eq!(deserialize_with::<T, BitSliceImpl>(&bytes), Err(E::Eof.e()));
+        assert_eq!(
+            deserialize_with::<T, DeVec>(&bytes, config),
+            Err(E::Eof.e())
+        );
+        assert_eq!(
+            deserialize_with::<T, BitSliceImpl>(&bytes, config),
+            Err(E::Eof.e())
+        );
+        assert_eq!(
+            config.deserialize_from_reader::<T>(bytes.as_slice()),
+            Err(E::Eof.e())
+        );
+    }
+}
+
+fn the_same_inner<T: Clone + Debug + PartialEq + Serialize + DeserializeOwned>(t: T) {
+    for config in [
+        Config::new(),
+        Config::new().with_aligned_strings(),
+        Config::new().with_varint_encoding(),
+        Config::new().with_varint_lengths(),
+        Config::new().with_columnar_layout(),
+        Config::new().with_self_describing_encoding(),
+        Config::new()
+            .with_self_describing_encoding()
+            .with_aligned_strings()
+            .with_columnar_layout(),
+        Config::new().with_skippable_fields().with_columnar_layout(),
+    ] {
+        the_same_config(&t, &config);
     }
 }
 
@@ -89,6 +160,772 @@ fn test_reddit() {
     assert_eq!(crate::serialize(&Variant::Three).unwrap().len(), 1);
 }
 
+#[test]
+fn test_variant_count() {
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Nine {
+        A,
+        B(u8),
+        C { c: bool },
+        D,
+        E,
+        F,
+        G,
+        H,
+        I(()),
+    }
+
+    let config = Config::new().with_variant_count("Nine", 9);
+    assert_eq!(crate::serialized_size_bits(&Nine::I(())).unwrap(), 7);
+    assert_eq!(config.serialized_size_bits(&Nine::I(())).unwrap(), 4);
+    assert_eq!(config.serialized_size_bits(&Nine::A).unwrap(), 4);
+    assert_eq!(
+        config.serialized_size_bits(&[Some(Nine::B(1))]).unwrap(),
+        13
+    );
+
+    let all = vec![
+        Nine::A,
+        Nine::B(5),
+        Nine::C { c: true },
+        Nine::D,
+        Nine::E,
+        Nine::F,
+        Nine::G,
+        Nine::H,
+        Nine::I(()),
+    ];
+    for config in [config.clone(), config.clone().with_columnar_layout()] {
+        the_same_config(&all, &config);
+    }
+
+    // Enums with 1 variant take no bits.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum One {
+        A(u8),
+    }
+    let config = Config::new().with_variant_count("One", 1);
+    assert_eq!(config.serialized_size_bits(&One::A(3)).unwrap(), 8);
+    the_same_config(&One::A(3), &config);
+
+    // The count must match the enum.
+    let config = Config::new().with_variant_count("Nine", 8);
+    assert_eq!(
+        config.serialize(&Nine::I(())),
+        Err(E::Invalid("variant index").e())
+    );
+    let bytes = config.serialize(&Nine::A).unwrap();
+    assert_eq!(
+        config.deserialize::<Nine>(&bytes),
+        Err(E::Invalid("variant count").e())
+    );
+
+    // Indices past the last variant are rejected.
+    let config = Config::new().with_variant_count("Nine", 9);
+    assert_eq!(
+        config.deserialize::<Nine>(&[0b1111]),
+        Err(E::Invalid("variant index").e())
+    );
+
+    // Registering the same name again only works with the same count.
+    let config = config.with_variant_count("Nine", 9);
+    assert_eq!(config.variant_counts.len(), 1);
+    let result = std::panic::catch_unwind(|| config.with_variant_count("Nine", 8));
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_bits() {
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Packed {
+        #[serde(with = "crate::bits::<5>")]
+        a: u8,
+        #[serde(with = "crate::bits::<12>")]
+        b: u16,
+        #[serde(with = "crate::bits::<0>")]
+        c: u32,
+        #[serde(with = "crate::bits::<64>")]
+        d: u64,
+        e: bool,
+    }
+
+    let packed = Packed {
+        a: 31,
+        b: 1000,
+        c: 0,
+        d: u64::MAX,
+        e: true,
+    };
+    assert_eq!(crate::serialized_size_bits(&packed).unwrap(), 82);
+    the_same(packed.clone());
+    the_same(vec![packed.clone(); 3]);
+
+    // Values that don't fit are rejected instead of truncated.
+    assert_eq!(
+        crate::serialize(&Packed {
+            a: 32,
+            ..packed.clone()
+        }),
+        Err(E::Invalid("bits").e())
+    );
+    assert_eq!(
+        crate::serialize(&Packed {
+            c: 1,
+            ..packed.clone()
+        }),
+        Err(E::Invalid("bits").e())
+    );
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Wide(#[serde(with = "crate::bits::<12>")] u16);
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Narrow(#[serde(with = "crate::bits::<12>")] u8);
+
+    let bytes = crate::serialize(&Wide(255)).unwrap();
+    assert_eq!(crate::deserialize::<Narrow>(&bytes).unwrap(), Narrow(255));
+    let bytes = crate::serialize(&Wide(256)).unwrap();
+    assert_eq!(
+        crate::deserialize::<Narrow>(&bytes),
+        Err(E::Invalid("bits").e())
+    );
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct TooWide(#[serde(with = "crate::bits::<65>")] u64);
+    assert_eq!(
+        crate::serialize(&TooWide(0)),
+        Err(E::NotSupported("bits > 64").e())
+    );
+}
+
+#[test]
+fn test_quantized() {
+    use crate::{Quantized, QuantizedAngle, QuantizedUnitVector, RoundDown, RoundUp, Rounding};
+    use std::f64::consts::TAU;
+
+    fn round_trip<T: Serialize + DeserializeOwned>(t: &T) -> T {
+        crate::deserialize(&crate::serialize(t).unwrap()).unwrap()
+    }
+
+    fn check_quantized<R: Rounding + Debug + PartialEq>() {
+        type Q<R> = Quantized<f64, -10, 10, 6, R>;
+        assert_eq!(crate::serialized_size_bits(&Q::<R>::new(0.0)).unwrap(), 6);
+        assert_eq!(Q::<R>::step(), 20.0 / 62.0);
+
+        for i in 0..=1000 {
+            let v = -10.0 + i as f64 * 0.02;
+            let q = Q::<R>::new(v);
+            let decoded = round_trip(&q);
+            assert_eq!(decoded, Q::new(q.quantized()));
+            assert!(
+                (decoded.get() - v).abs() <= Q::<R>::max_error() + 1e-9,
+                "{v}"
+            );
+        }
+        assert_eq!(round_trip(&Q::<R>::new(-10.0)).get(), -10.0);
+        assert_eq!(round_trip(&Q::<R>::new(10.0)).get(), 10.0);
+        assert_eq!(round_trip(&Q::<R>::new(0.0)).get(), 0.0);
+        assert_eq!(
+            Quantized::<f32, -1, 1, 1, R>::new(0.3).quantized().abs(),
+            1.0
+        );
+
+        // Out of range values are clamped.
+        assert_eq!(Q::<R>::new(-1e9).quantized(), -10.0);
+        assert_eq!(Q::<R>::new(f64::INFINITY).quantized(), 10.0);
+        assert_eq!(Q::<R>::new(f64::NAN).quantized(), -10.0);
+
+        type A<R> = QuantizedAngle<f64, 5, R>;
+        assert_eq!(crate::serialized_size_bits(&A::<R>::new(1.0)).unwrap(), 5);
+        for i in -1000..=1000 {
+            let v = i as f64 * 0.01;
+            let decoded = round_trip(&A::<R>::new(v)).get();
+            assert!((0.0..TAU).contains(&decoded));
+            let diff = (decoded - v).rem_euclid(TAU);
+            let error = diff.min(TAU - diff);
+            assert!(error <= A::<R>::max_error() + 1e-9, "{v}");
+        }
+        assert_eq!(round_trip(&A::<R>::new(0.0)).get(), 0.0);
+
+        type V<R> = QuantizedUnitVector<f32, 8, R>;
+        assert_eq!(
+            crate::serialized_size_bits(&V::<R>::new([1.0, 0.0, 0.0])).unwrap(),
+            16
+        );
+        for i in 0..50 {
+            for j in 0..50 {
+                // Spherical coordinates covering every octant.
+                let (theta, phi) = (i as f32 * 0.13, j as f32 * 0.0641);
+                let v = [phi.sin() * theta.cos(), phi.sin() * theta.sin(), phi.cos()];
+                let decoded = round_trip(&V::<R>::new(v)).get();
+                assert_eq!(decoded, V::<R>::new(v).quantized());
+                let length = decoded.iter().map(|d| d * d).sum::<f32>().sqrt();
+                assert!((length - 1.0).abs() < 1e-6);
+                let error = v
+                    .iter()
+                    .zip(decoded)
+                    .map(|(a, b)| (a - b) * (a - b))
+                    .sum::<f32>()
+                    .sqrt();
+                assert!(error as f64 <= V::<R>::max_error() + 1e-6, "{v:?}");
+            }
+        }
+
+        // The axes are exact.
+        for i in 0..6 {
+            let mut axis = [0.0; 3];
+            axis[i % 3] = if i < 3 { 1.0 } else { -1.0 };
+            assert_eq!(round_trip(&V::<R>::new(axis)).get(), axis);
+        }
+    }
+    check_quantized::<crate::RoundNearest>();
+    check_quantized::<RoundDown>();
+    check_quantized::<RoundUp>();
+
+    // Rounding down and up bound the value.
+    type Health<R> = Quantized<f32, 0, 1, 4, R>;
+    for i in 0..=100 {
+        let v = i as f32 / 100.0;
+        assert!(Health::<RoundDown>::new(v).quantized() <= v);
+        assert!(Health::<RoundUp>::new(v).quantized() >= v);
+    }
+    assert_eq!(
+        Quantized::<f32, 0, 1, 4>::max_error(),
+        Health::<RoundDown>::max_error() / 2.0
+    );
+    the_same(vec![Health::<RoundUp>::new(1.0); 3]);
+
+    // The greatest 4 bit value isn't on the grid.
+    assert!(crate::deserialize::<Health<RoundUp>>(&[0b1111]).is_err());
+    assert_eq!(Health::<RoundUp>::default(), Health::new(0.0));
+    assert_eq!(QuantizedAngle::<f32, 5>::default().get(), 0.0);
+}
+
+#[test]
+fn test_self_describing() {
+    use serde::de::IgnoredAny;
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Inner {
+        A,
+        B(u16),
+        C(i8, Option<String>),
+        D { d: Vec<u8> },
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Untagged {
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        Char(char),
+        String(String),
+        Tuple(u8, u8),
+        Inner(Inner),
+        Map(BTreeMap<u32, Option<Box<Untagged>>>),
+        Bits {
+            #[serde(with = "crate::bits::<5>")]
+            bits: u8,
+            wide: u64,
+        },
+        Unit,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    enum Internal {
+        A,
+        B { b: u32 },
+        C(Inner),
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "t", content = "c")]
+    enum Adjacent {
+        A,
+        B(u128, #[serde(with = "crate::bits::<5>")] u8),
+        C { c: Internal },
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Flatten {
+        a: u8,
+        #[serde(flatten)]
+        rest: BTreeMap<String, Untagged>,
+    }
+
+    let untagged = vec![
+        Untagged::Bool(true),
+        Untagged::Int(-5),
+        Untagged::Float(0.5),
+        Untagged::Char('b'),
+        Untagged::String("abc".to_owned()),
+        Untagged::Tuple(1, 2),
+        Untagged::Inner(Inner::A),
+        Untagged::Inner(Inner::B(3)),
+        Untagged::Inner(Inner::C(-4, Some("d".to_owned()))),
+        Untagged::Inner(Inner::D { d: vec![1, 2, 3] }),
+        Untagged::Map(BTreeMap::from([
+            (1, None),
+            (2, Some(Box::new(Untagged::Unit))),
+        ])),
+        Untagged::Bits {
+            bits: 31,
+            wide: u64::MAX,
+        },
+        Untagged::Unit,
+    ];
+    let internal = vec![Internal::A, Internal::B { b: 7 }, Internal::C(Inner::B(8))];
+    let adjacent = vec![
+        Adjacent::A,
+        Adjacent::B(u128::MAX, 31),
+        Adjacent::C { c: Internal::A },
+    ];
+    let flatten = Flatten {
+        a: 1,
+        rest: BTreeMap::from([
+            ("b".to_owned(), Untagged::Int(2)),
+            ("c".to_owned(), Untagged::String("three".to_owned())),
+        ]),
+    };
+
+    let configs = [
+        Config::new().with_self_describing_encoding(),
+        Config::new()
+            .with_self_describing_encoding()
+            .with_varint_encoding()
+            .with_variant_count("Inner", 4),
+    ];
+    for config in &configs {
+        the_same_config(&untagged, config);
+        the_same_config(&internal, config);
+        the_same_config(&adjacent, config);
+        the_same_config(&flatten, config);
+
+        // Any value can be skipped.
+        let bytes = config.serialize(&(&untagged, &internal, 5u8)).unwrap();
+        assert_eq!(
+            config.deserialize::<(IgnoredAny, IgnoredAny, u8)>(&bytes),
+            Ok((IgnoredAny, IgnoredAny, 5))
+        );
+
+        // Struct fields are matched by name and unknown fields are skipped.
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Old {
+            a: u8,
+            b: Vec<Inner>,
+            c: String,
+        }
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct New {
+            c: String,
+            a: u8,
+            #[serde(default)]
+            d: bool,
+        }
+        let old = Old {
+            a: 1,
+            b: vec![Inner::A, Inner::D { d: vec![2] }],
+            c: "3".to_owned(),
+        };
+        let bytes = config.serialize(&old).unwrap();
+        assert_eq!(
+            config.deserialize::<New>(&bytes),
+            Ok(New {
+                c: "3".to_owned(),
+                a: 1,
+                d: false
+            })
+        );
+
+        // Fields past the end of a tuple are skipped.
+        let bytes = config.serialize(&(1u8, "2", 3u8)).unwrap();
+        assert_eq!(config.deserialize::<(u8,)>(&bytes), Ok((1,)));
+        assert_eq!(
+            config
+                .deserialize::<(u8, u8, u8, u8)>(&bytes)
+                .map_err(|e| e.kind()),
+            Err(ErrorKind::Custom)
+        );
+        // Variants must be of the same kind.
+        let bytes = config.serialize(&Inner::B(1)).unwrap();
+        assert_eq!(
+            config.deserialize::<Internal>(&bytes).map_err(|e| e.kind()),
+            Err(ErrorKind::Custom)
+        );
+    }
+
+    // Only 29 of the 32 tags are used.
+    let config = &configs[0];
+    assert_eq!(
+        config.deserialize::<IgnoredAny>(&[0b11111]),
+        Err(E::Invalid("tag").e())
+    );
+
+    // The tagless encoding doesn't support deserialize_any.
+    let bytes = crate::serialize(&untagged).unwrap();
+    assert_eq!(
+        crate::deserialize::<Vec<Untagged>>(&bytes),
+        Err(E::NotSupported("deserialize_any").e())
+    );
+    assert_eq!(
+        crate::deserialize::<IgnoredAny>(&[]),
+        Err(E::NotSupported("deserialize_ignored_any").e())
+    );
+}
+
+#[test]
+fn test_skippable_fields() {
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Skippable {
+        a: u8,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        b: Option<u8>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        c: Vec<u16>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        d: Option<Box<Skippable>>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Variant {
+        A {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            a: Option<String>,
+            b: bool,
+        },
+        B(Skippable),
+    }
+
+    let none = Skippable {
+        a: 1,
+        b: None,
+        c: vec![],
+        d: None,
+    };
+    let some = Skippable {
+        a: 2,
+        b: Some(3),
+        c: vec![4, 5],
+        d: Some(Box::new(none.clone())),
+    };
+    let variants = vec![
+        Variant::A { a: None, b: true },
+        Variant::A {
+            a: Some("a".to_owned()),
+            b: false,
+        },
+        Variant::B(none.clone()),
+        Variant::B(some.clone()),
+    ];
+
+    for config in [
+        Config::new().with_skippable_fields(),
+        Config::new()
+            .with_skippable_fields()
+            .with_columnar_layout()
+            .with_varint_encoding(),
+        Config::new().with_self_describing_encoding(),
+    ] {
+        the_same_config(&none, &config);
+        the_same_config(&some, &config);
+        the_same_config(&variants, &config);
+        the_same_config(&vec![none.clone(), some.clone(), none.clone()], &config);
+    }
+
+    // The number of present fields, a bit before each of them and nothing for trailing skipped
+    // fields.
+    let config = Config::new().with_skippable_fields();
+    assert_eq!(config.serialized_size_bits(&none).unwrap(), 3 + 1 + 8);
+    assert_eq!(config.serialized_size_bits(&(1u8, 2u8)).unwrap(), 16);
+
+    // Fields without #[serde(default)] can't be absent unless they're options.
+    #[derive(Debug, PartialEq, Serialize)]
+    struct Skipped {
+        #[serde(skip_serializing_if = "str::is_empty")]
+        a: &'static str,
+    }
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Required {
+        #[allow(unused)]
+        a: String,
+    }
+    let bytes = config.serialize(&Skipped { a: "" }).unwrap();
+    assert_eq!(
+        config.deserialize::<Required>(&bytes).map_err(|e| e.kind()),
+        Err(ErrorKind::Custom)
+    );
+
+    assert_eq!(
+        crate::serialize(&none),
+        Err(E::NotSupported("skip_field").e())
+    );
+}
+
+#[test]
+fn test_skippable_fields_alias() {
+    // Aliases are in the fields passed to deserialize_struct but aren't serialized.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Aliased {
+        #[serde(alias = "zzz")]
+        a: u8,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        b: Option<u8>,
+        c: u8,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Variant {
+        A {
+            #[serde(alias = "x", alias = "y")]
+            a: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            b: Option<u8>,
+            c: u8,
+        },
+    }
+
+    let none = Aliased {
+        a: 1,
+        b: None,
+        c: 2,
+    };
+    let some = Aliased {
+        a: 3,
+        b: Some(4),
+        c: 5,
+    };
+    let variants = vec![
+        Variant::A {
+            a: 1,
+            b: None,
+            c: 2,
+        },
+        Variant::A {
+            a: 3,
+            b: Some(4),
+            c: 5,
+        },
+    ];
+
+    for config in [
+        Config::new().with_skippable_fields(),
+        Config::new().with_skippable_fields().with_columnar_layout(),
+    ] {
+        the_same_config(&none, &config);
+        the_same_config(&some, &config);
+        the_same_config(&variants, &config);
+        the_same_config(&vec![none.clone(), some.clone(), none.clone()], &config);
+    }
+}
+
+#[test]
+fn test_versioned() {
+    let value = (5u32, "abc".to_owned(), vec![Some(1u8), None]);
+    let encoded = crate::serialize_versioned(&value, 7).unwrap();
+    assert_eq!(encoded[..4], FORMAT_VERSION.to_le_bytes());
+    assert_eq!(encoded[4..8], 7u32.to_le_bytes());
+    assert_eq!(encoded[8..], crate::serialize(&value).unwrap());
+    assert_eq!(crate::deserialize_versioned(&encoded, 7), Ok(value.clone()));
+
+    assert_eq!(
+        crate::deserialize_versioned::<(u32, String, Vec<Option<u8>>)>(&encoded, 8),
+        Err(E::VersionMismatch("schema").e())
+    );
+    let mut other_format = encoded.clone();
+    other_format[0] ^= 1;
+    assert_eq!(
+        crate::deserialize_versioned::<(u32, String, Vec<Option<u8>>)>(&other_format, 7),
+        Err(E::VersionMismatch("format").e())
+    );
+    // The versions are checked before the value, even one that wouldn't deserialize.
+    assert_eq!(
+        crate::deserialize_versioned::<String>(&encoded, 8),
+        Err(E::VersionMismatch("schema").e())
+    );
+    assert_eq!(
+        crate::deserialize_versioned::<()>(&encoded[..6], 7),
+        Err(E::Eof.e())
+    );
+
+    let config = Config::new().with_varint_encoding().with_columnar_layout();
+    let encoded = config.serialize_versioned(&value, 0).unwrap();
+    assert_eq!(encoded[8..], config.serialize(&value).unwrap());
+    assert_eq!(config.deserialize_versioned(&encoded, 0), Ok(value.clone()));
+
+    // The limit includes the versions.
+    let config = config.with_limit(encoded.len() - 1);
+    assert_eq!(
+        config.serialize_versioned(&value, 0),
+        Err(E::LimitExceeded("size").e())
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive() {
+    use crate::{decode, encode, Decode, Encode};
+    use std::collections::{BTreeMap, BTreeSet, VecDeque};
+    use std::marker::PhantomData;
+
+    fn the_same_encode<T: Debug + PartialEq + Serialize + DeserializeOwned + Encode + Decode>(
+        t: T,
+    ) {
+        let encoded = encode(&t).unwrap();
+        assert_eq!(encoded, crate::serialize(&t).unwrap(), "{t:?}");
+        assert_eq!(decode::<T>(&encoded).unwrap(), t);
+
+        // Errors match deserialize's too.
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(decode::<T>(&trailing), crate::deserialize::<T>(&trailing));
+        if let Some(shorter) = encoded.get(..encoded.len().saturating_sub(1)) {
+            assert_eq!(
+                decode::<T>(shorter).is_err(),
+                crate::deserialize::<T>(shorter).is_err()
+            );
+        }
+
+        // So do the depths they accept.
+        for depth in 0..8 {
+            let config = Config::new().with_max_depth(depth);
+            assert_eq!(
+                config.decode::<T>(&encoded),
+                config.deserialize::<T>(&encoded),
+                "{t:?} {depth}"
+            );
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+    struct Unit;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+    struct Tuple(u8, i64, char);
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+    struct Generic<T> {
+        t: T,
+        phantom: PhantomData<T>,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+    enum Enum {
+        A,
+        B(String),
+        C { x: f32, y: Option<Box<Enum>> },
+        D(Unit, Tuple),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+    struct Everything {
+        a: bool,
+        b: (u16, i16, u32, i32),
+        c: (u128, i128, usize, isize),
+        d: f64,
+        e: [Enum; 3],
+        f: Vec<Generic<Result<u8, ()>>>,
+        g: HashMap<u8, String>,
+        h: BTreeMap<String, Vec<u8>>,
+        i: BTreeSet<i8>,
+        j: VecDeque<()>,
+    }
+
+    the_same_encode(Unit);
+    the_same_encode(Tuple(1, -2, 'c'));
+    the_same_encode(Enum::A);
+    the_same_encode(Enum::C {
+        x: 1.5,
+        y: Some(Box::new(Enum::B("abc".to_owned()))),
+    });
+    the_same_encode(vec![Enum::D(Unit, Tuple(0, i64::MIN, '\u{10FFFF}')); 10]);
+    the_same_encode(Everything {
+        a: true,
+        b: (1, -1, u32::MAX, i32::MIN),
+        c: (u128::MAX, -5, 1000, -1000),
+        d: -0.5,
+        e: [Enum::A, Enum::B("é".repeat(10)), Enum::A],
+        f: vec![
+            Generic {
+                t: Ok(5),
+                phantom: PhantomData,
+            },
+            Generic {
+                t: Err(()),
+                phantom: PhantomData,
+            },
+        ],
+        g: HashMap::from([(1, "a".to_owned())]),
+        h: BTreeMap::from([("b".to_owned(), vec![1, 2, 3])]),
+        i: BTreeSet::from([-1, 0, 1]),
+        j: VecDeque::from(vec![(); 100]),
+    });
+
+    #[allow(dead_code)]
+    #[derive(Encode)]
+    enum Five {
+        A,
+        B,
+        C,
+        D,
+        E,
+    }
+    assert_eq!(
+        decode::<Enum>(&encode(&Five::E).unwrap()),
+        Err(E::Invalid("variant index").e())
+    );
+    assert_eq!(decode::<String>(&[]), Err(E::Eof.e()));
+
+    // Config::decode applies the depth and allocation limits to malicious inputs.
+    #[allow(dead_code)]
+    #[derive(Debug, PartialEq, Serialize, Deserialize, Encode, Decode)]
+    enum Tree {
+        Leaf,
+        Node(Box<Tree>, Box<Tree>),
+    }
+    let hostile = vec![0b1010_1010; 1 << 20];
+    assert_eq!(
+        Config::new().with_max_depth(64).decode::<Tree>(&hostile),
+        Err(E::LimitExceeded("depth").e())
+    );
+    let tree = Tree::Node(Box::new(Tree::Leaf), Box::new(Tree::Leaf));
+    let bytes = encode(&tree).unwrap();
+    assert_eq!(Config::new().with_max_depth(1).decode::<Tree>(&bytes), Ok(tree));
+    assert_eq!(
+        Config::new().with_max_depth(0).decode::<Tree>(&bytes),
+        Err(E::LimitExceeded("depth").e())
+    );
+    the_same_encode(Tree::Node(
+        Box::new(Tree::Node(Box::new(Tree::Leaf), Box::new(Tree::Leaf))),
+        Box::new(Tree::Leaf),
+    ));
+    the_same_encode(HashMap::from([(1u8, vec![(2u16, Some(Unit))])]));
+
+    let long = encode(&vec![0u64; 100]).unwrap();
+    assert_eq!(
+        Config::new()
+            .with_allocation_limit(799)
+            .decode::<Vec<u64>>(&long),
+        Err(E::LimitExceeded("allocation").e())
+    );
+    assert_eq!(
+        Config::new()
+            .with_allocation_limit(800)
+            .decode::<Vec<u64>>(&long),
+        Ok(vec![0; 100])
+    );
+    let s = encode("abc").unwrap();
+    assert_eq!(
+        Config::new().with_allocation_limit(2).decode::<String>(&s),
+        Err(E::LimitExceeded("allocation").e())
+    );
+    assert_eq!(
+        Config::new().with_limit(s.len() - 1).decode::<String>(&s),
+        Err(E::LimitExceeded("size").e())
+    );
+}
+
 #[test]
 fn test_negative_isize() {
     the_same_inner(-5isize);
@@ -106,6 +943,963 @@ fn test_long_string() {
     the_same("abcde".repeat(25))
 }
 
+#[test]
+fn test_unknown_len() {
+    use serde::ser::SerializeMap;
+    use serde::Serializer;
+
+    // Hides the lengths of the sequences from the serializer.
+    #[derive(Clone)]
+    struct Odd(Vec<Vec<u16>>);
+    struct OddInner<'a>(&'a [u16]);
+
+    impl Serialize for Odd {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().filter(|v| !v.is_empty()).map(|v| OddInner(v)))
+        }
+    }
+
+    impl Serialize for OddInner<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().filter(|&v| v % 2 == 1))
+        }
+    }
+
+    // Hides the length of the map from the serializer.
+    struct UnknownMap(Vec<(u8, String)>);
+
+    impl Serialize for UnknownMap {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut map = serializer.serialize_map(None)?;
+            for (k, v) in &self.0 {
+                map.serialize_entry(k, v)?;
+            }
+            map.end()
+        }
+    }
+
+    for i in 0..20 {
+        let v: Vec<Vec<u16>> = (0..i).map(|j| (j..j * 3).collect()).collect();
+        let expected: Vec<Vec<u16>> = v
+            .iter()
+            .filter(|v| !v.is_empty())
+            .map(|v| v.iter().copied().filter(|v| v % 2 == 1).collect())
+            .collect();
+
+        let bytes = crate::serialize(&Odd(v.clone())).unwrap();
+        assert_eq!(bytes, crate::serialize(&expected).unwrap());
+        assert_eq!(
+            serialize_with::<BitVecImpl>(&Odd(v.clone()), &Config::new()).unwrap(),
+            bytes
+        );
+        #[cfg(target_endian = "little")]
+        assert_eq!(
+            serialize_with::<SerVec>(&Odd(v), &Config::new()).unwrap(),
+            bytes
+        );
+        assert_eq!(deserialize::<Vec<Vec<u16>>>(&bytes).unwrap(), expected);
+
+        let entries: Vec<(u8, String)> =
+            (0..i as u8).map(|j| (j, "a".repeat(j as usize))).collect();
+        let bytes = crate::serialize(&UnknownMap(entries.clone())).unwrap();
+        let expected: std::collections::BTreeMap<u8, String> = entries.into_iter().collect();
+        assert_eq!(bytes, crate::serialize(&expected).unwrap());
+        assert_eq!(
+            deserialize::<std::collections::BTreeMap<u8, String>>(&bytes).unwrap(),
+            expected
+        );
+    }
+
+    // Hides the length of a sequence of sequences nested any number of levels deep.
+    #[derive(Clone)]
+    struct Hidden<I>(I);
+
+    impl<I: Iterator + Clone> Serialize for Hidden<I>
+    where
+        I::Item: Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.clone().filter(|_| true))
+        }
+    }
+
+    for i in 0..10 {
+        let v: Vec<Vec<Vec<u16>>> = (0..i)
+            .map(|j| (0..j).map(|k| (0..k * 7).collect()).collect())
+            .collect();
+        let hidden = Hidden(v.iter().map(|v| Hidden(v.iter().map(|v| Hidden(v.iter())))));
+        let bytes = crate::serialize(&hidden).unwrap();
+        assert_eq!(bytes, crate::serialize(&v).unwrap());
+        assert_eq!(
+            serialize_with::<BitVecImpl>(&hidden, &Config::new()).unwrap(),
+            bytes
+        );
+    }
+}
+
+#[test]
+fn test_encoder_decoder() {
+    let mut encoder = Encoder::new();
+    let mut decoder = Decoder::new();
+    let mut bit_vec = BitVecImpl::default();
+
+    // Shrinking messages make sure that stale bits are cleared.
+    for i in (0..100).rev().chain(0..100) {
+        let v: Vec<String> = (0..i % 7).map(|j| "a".repeat(i * j)).collect();
+        let expected = crate::serialize(&v).unwrap();
+        assert_eq!(
+            serialize_in(&v, &mut bit_vec, &Config::new()).unwrap(),
+            expected
+        );
+
+        let bytes = encoder.encode(&v).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(decoder.decode::<Vec<String>>(bytes).unwrap(), v);
+        assert_eq!(
+            decoder.decode::<Vec<String>>(&expected[..expected.len() - 1]),
+            Err(E::Eof.e())
+        );
+    }
+}
+
+#[test]
+fn test_borrowed() {
+    use serde::de::{Deserializer, Visitor};
+    use serde::Serializer;
+
+    // [u8] serializes a sequence, so it can't be borrowed.
+    #[derive(Debug, PartialEq)]
+    struct Bytes<'a>(&'a [u8]);
+
+    impl Serialize for Bytes<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(self.0)
+        }
+    }
+
+    impl<'de: 'a, 'a> Deserialize<'de> for Bytes<'a> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct BytesVisitor;
+            impl<'de> Visitor<'de> for BytesVisitor {
+                type Value = Bytes<'de>;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("borrowed bytes")
+                }
+
+                fn visit_borrowed_bytes<E>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+                    Ok(Bytes(v))
+                }
+            }
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Borrowed<'a> {
+        b: bool,
+        s: &'a str,
+        #[serde(borrow)]
+        o: Option<&'a str>,
+        #[serde(borrow)]
+        bytes: Bytes<'a>,
+    }
+
+    let config = Config::new().with_aligned_strings();
+    let value = Borrowed {
+        b: true,
+        s: "abcde",
+        o: Some("å"),
+        bytes: Bytes(&[1, 2, 3]),
+    };
+
+    let serialized = config.serialize(&value).unwrap();
+    assert_eq!(
+        serialize_with::<BitVecImpl>(&value, &config).unwrap(),
+        serialized
+    );
+
+    let in_input = |s: &[u8]| serialized.as_ptr_range().contains(&s.as_ptr());
+    let check = |decoded: Borrowed| {
+        assert_eq!(decoded, value);
+        assert!(in_input(decoded.s.as_bytes()));
+        assert!(in_input(decoded.o.unwrap().as_bytes()));
+        assert!(in_input(decoded.bytes.0));
+    };
+    check(deserialize_with::<Borrowed, BitSliceImpl>(&serialized, &config).unwrap());
+    #[cfg(target_endian = "little")]
+    check(deserialize_with::<Borrowed, DeVec>(&serialized, &config).unwrap());
+
+    // Padding must be zero.
+    let mut bytes = serialized.clone();
+    bytes[0] |= 0b10000000;
+    assert_eq!(
+        config.deserialize::<Borrowed>(&bytes),
+        Err(E::Invalid("padding").e())
+    );
+
+    // Borrowing requires the option.
+    assert!(crate::deserialize::<Borrowed>(&crate::serialize(&value).unwrap()).is_err());
+
+    // The length of the sequence would change the alignment.
+    struct UnknownLen;
+    impl Serialize for UnknownLen {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(["a"].iter().filter(|_| true))
+        }
+    }
+    assert_eq!(
+        config.serialize(&UnknownLen),
+        Err(E::NotSupported("unknown len with aligned strings").e())
+    );
+}
+
+#[test]
+fn test_varint() {
+    let config = Config::new().with_varint_encoding();
+    let bits = |v: u64| {
+        let a = serialize_with::<BitVecImpl>(&v, &config).unwrap();
+        let b = serialize_with::<BitVecImpl>(&(v as i128 / 2), &config).unwrap();
+        let c = serialize_with::<BitVecImpl>(&(-(v as i128 + 1) / 2), &config).unwrap();
+        assert_eq!((a.len(), a.len()), (b.len(), c.len()));
+        a.len()
+    };
+    assert_eq!(bits(0), 1);
+    assert_eq!(bits(100), 2);
+    assert_eq!(bits(u32::MAX as u64), 6);
+    assert_eq!(bits(u64::MAX), 10);
+
+    for shift in 0..128 {
+        the_same_config(&(1u128 << shift), &config);
+        the_same_config(&((1u128 << shift) - 1), &config);
+        the_same_config(&(1i128 << shift), &config);
+        the_same_config(&(1i128 << shift).wrapping_sub(1), &config);
+    }
+    for shift in 0..64 {
+        the_same_config(&(1u64 << shift), &config);
+        the_same_config(&(-1i64 << shift), &config);
+        the_same_config(&((1u16 << (shift % 16)), 1i32 << (shift % 32)), &config);
+    }
+
+    // More significant bits than u16 has.
+    let bytes = config.serialize(&(u16::MAX as u32 + 1)).unwrap();
+    assert_eq!(
+        config.deserialize::<u16>(&bytes),
+        Err(E::Invalid("varint").e())
+    );
+}
+
+#[test]
+fn test_config() {
+    let value = (5u32, "abcde".to_owned());
+    let limit = crate::serialize(&value).unwrap().len();
+
+    let config = Config::new().with_limit(limit);
+    let bytes = config.serialize(&value).unwrap();
+    assert_eq!(config.deserialize(&bytes), Ok(value.clone()));
+
+    let config = Config::new().with_limit(limit - 1);
+    assert_eq!(config.serialize(&value), Err(E::LimitExceeded("size").e()));
+    assert_eq!(
+        Encoder::with_config(config.clone()).encode(&value),
+        Err(E::LimitExceeded("size").e())
+    );
+    assert_eq!(
+        config.deserialize::<(u32, String)>(&bytes),
+        Err(E::LimitExceeded("size").e())
+    );
+    assert_eq!(
+        Decoder::with_config(config.clone()).decode::<(u32, String)>(&bytes),
+        Err(E::LimitExceeded("size").e())
+    );
+
+    let mut trailing = bytes.clone();
+    trailing.extend([1, 2, 3]);
+    let config = Config::new().allow_trailing_bytes();
+    assert_eq!(config.deserialize(&trailing), Ok(value.clone()));
+    assert_eq!(
+        Decoder::with_config(config.clone()).decode(&trailing),
+        Ok(value.clone())
+    );
+    assert_eq!(
+        config.deserialize::<(u32, String)>(&bytes[..bytes.len() - 1]),
+        Err(E::Eof.e())
+    );
+    assert_eq!(
+        config
+            .reject_trailing_bytes()
+            .deserialize::<(u32, String)>(&trailing),
+        Err(E::ExpectedEof.e())
+    );
+
+    let config = Config::new().with_varint_lengths();
+    assert_eq!(config.serialize(&vec![(); 100]).unwrap().len(), 2);
+    assert_eq!(crate::serialize(&vec![(); 100]).unwrap().len(), 2);
+    assert_eq!(config.serialize(&vec![(); 100000]).unwrap().len(), 4);
+    assert_eq!(crate::serialize(&vec![(); 100000]).unwrap().len(), 5);
+}
+
+#[test]
+fn test_allocation_limit() {
+    // Length of 2^40 - 1 followed by a few bytes.
+    let hostile = [0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0];
+    assert_eq!(deserialize::<String>(&hostile), Err(E::Eof.e()));
+    assert_eq!(deserialize::<Vec<u8>>(&hostile), Err(E::Eof.e()));
+
+    let value = vec!["ab".to_owned(); 3];
+    let bytes = crate::serialize(&value).unwrap();
+
+    // 3 strings + 3 * 2 bytes.
+    let size = 3 * std::mem::size_of::<String>() + 3 * 2;
+    let config = Config::new().with_allocation_limit(size);
+    assert_eq!(config.deserialize(&bytes), Ok(value));
+    assert_eq!(
+        config
+            .with_allocation_limit(size - 1)
+            .deserialize::<Vec<String>>(&bytes),
+        Err(E::LimitExceeded("allocation").e())
+    );
+
+    // Large elements count as their size, even if they take few bits.
+    let value = vec![[0u64; 32]; 16];
+    let bytes = Config::new()
+        .with_varint_encoding()
+        .serialize(&value)
+        .unwrap();
+    assert_eq!(bytes.len(), 66); // 1 bit per u64 and 9 bits of length.
+    let config = Config::new().with_varint_encoding();
+    let size = 16 * std::mem::size_of::<[u64; 32]>();
+    for (limit, expected) in [
+        (size, Ok(value)),
+        (size - 1, Err(E::LimitExceeded("allocation").e())),
+    ] {
+        assert_eq!(
+            config
+                .clone()
+                .with_allocation_limit(limit)
+                .deserialize::<Vec<[u64; 32]>>(&bytes),
+            expected
+        );
+    }
+
+    let mut map = HashMap::new();
+    map.insert(1u8, ());
+    map.insert(2u8, ());
+    let bytes = crate::serialize(&map).unwrap();
+    let config = Config::new().with_allocation_limit(1);
+    assert_eq!(
+        config.deserialize::<HashMap<u8, ()>>(&bytes),
+        Err(E::LimitExceeded("allocation").e())
+    );
+
+    // Columns count as their length and buffers, even though an empty one takes 1 bit.
+    let columns = 1 << 20;
+    let mut w = SerVec::default();
+    crate::ser::write_gamma(&mut w, 1).unwrap();
+    crate::ser::write_gamma(&mut w, columns).unwrap();
+    for _ in 0..columns {
+        crate::ser::write_gamma(&mut w, 0).unwrap();
+    }
+    let hostile = w.into_inner();
+    assert!(hostile.len() < columns);
+    let config = Config::new()
+        .with_columnar_layout()
+        .with_allocation_limit(4 * columns);
+    assert_eq!(
+        config.deserialize::<Vec<u8>>(&hostile),
+        Err(E::LimitExceeded("allocation").e())
+    );
+
+    let value = vec![(1u64, 2u64); 10];
+    let bytes = config.serialize(&value).unwrap();
+    assert_eq!(config.deserialize(&bytes), Ok(value));
+}
+
+#[test]
+fn test_error_kind() {
+    let config = Config::new();
+
+    let e = deserialize::<u8>(&[1, 2]).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::ExpectedEof);
+    assert_eq!(e.bit_position(), Some(8));
+    assert!(e.to_string().ends_with(" at bit 8"), "{e}");
+    assert_eq!(
+        deserialize_with::<u8, BitSliceImpl>(&[1, 2], &config)
+            .unwrap_err()
+            .bit_position(),
+        Some(8)
+    );
+
+    // A byte that isn't valid UTF-8 after a u8 and the string's length.
+    let bytes = crate::serialize(&(1u8, vec![u8::MAX])).unwrap();
+    let e = deserialize::<(u8, String)>(&bytes).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Invalid);
+    let position = e.bit_position().unwrap();
+    assert!(position > 16, "{position}");
+    assert_eq!(
+        deserialize_with::<(u8, String), BitSliceImpl>(&bytes, &config)
+            .unwrap_err()
+            .bit_position(),
+        Some(position)
+    );
+
+    #[derive(Serialize)]
+    struct Skipped {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        a: Option<u8>,
+    }
+    let e = crate::serialize(&Skipped { a: None }).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotSupported);
+    assert_eq!(e.bit_position(), None);
+
+    // Errors in columns are reported at their position in the input. The zero is at the end of
+    // the first column, which is followed by the 16 bits of the second one.
+    let config = Config::new().with_columnar_layout();
+    let value = vec![(5u8, 1u8), (0, 2)];
+    let bytes = config.serialize(&value).unwrap();
+    let e = config
+        .deserialize::<Vec<(std::num::NonZeroU8, u8)>>(&bytes)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Custom);
+    assert_eq!(
+        e.bit_position(),
+        Some(config.serialized_size_bits(&value).unwrap() - 16)
+    );
+
+    // Errors store their kind and position inline instead of allocating.
+    assert!(std::mem::size_of::<Error>() <= 5 * std::mem::size_of::<usize>());
+    assert_eq!(
+        std::mem::size_of::<Result<(), Error>>(),
+        std::mem::size_of::<Error>()
+    );
+}
+
+/// Counts how many elements were serialized.
+struct Counted<'a>(&'a std::cell::Cell<usize>);
+
+impl Serialize for Counted<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.set(self.0.get() + 1);
+        u64::MAX.serialize(serializer)
+    }
+}
+
+#[test]
+fn test_serialize_into_writer() {
+    struct Failing;
+
+    impl std::io::Write for Failing {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let value: Vec<String> = (0..1000).map(|i| "a".repeat(i % 50)).collect();
+    let mut bytes = vec![];
+    crate::serialize_into_writer(&mut bytes, &value).unwrap();
+    assert_eq!(bytes, crate::serialize(&value).unwrap());
+
+    assert_eq!(
+        crate::serialize_into_writer(Failing, &value),
+        Err(E::Io(std::io::ErrorKind::BrokenPipe).e())
+    );
+
+    let mut bytes = vec![];
+    assert_eq!(
+        Config::new()
+            .with_limit(100)
+            .serialize_into_writer(&mut bytes, &value),
+        Err(E::LimitExceeded("size").e())
+    );
+    assert!(bytes.len() <= 100);
+
+    // Serializing stops soon after the writer fails or the limit is exceeded instead of going
+    // through all the elements.
+    let count = std::cell::Cell::new(0);
+    let value: Vec<Counted> = (0..100_000).map(|_| Counted(&count)).collect();
+    assert_eq!(
+        crate::serialize_into_writer(Failing, &value),
+        Err(E::Io(std::io::ErrorKind::BrokenPipe).e())
+    );
+    assert!(count.get() < 1000, "{}", count.get());
+
+    count.set(0);
+    assert_eq!(
+        Config::new()
+            .with_limit(100)
+            .serialize_into_writer(std::io::sink(), &value),
+        Err(E::LimitExceeded("size").e())
+    );
+    assert!(count.get() < 20, "{}", count.get());
+}
+
+#[test]
+fn test_serialize_into_slice() {
+    // Serializing stops soon after the slice is full instead of going through all the elements.
+    let count = std::cell::Cell::new(0);
+    let value: Vec<Counted> = (0..1000).map(|_| Counted(&count)).collect();
+    let mut slice = [0; 16];
+    assert_eq!(
+        crate::serialize_into_slice(&value, &mut slice),
+        Err(E::BufferFull.e())
+    );
+    assert!(count.get() < 5, "{}", count.get());
+}
+
+#[test]
+fn test_deserialize_from_reader() {
+    // Returns 1 byte at a time and is interrupted in between.
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        interrupt: bool,
+    }
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            let Some((&first, rest)) = self.bytes.split_first() else {
+                return Ok(0);
+            };
+            self.bytes = rest;
+            buf[0] = first;
+            Ok(1)
+        }
+    }
+
+    let value: Vec<(String, u64)> = (0..1000).map(|i| ("a".repeat(i % 50), i as u64)).collect();
+    let bytes = crate::serialize(&value).unwrap();
+    let r = Trickle {
+        bytes: &bytes,
+        interrupt: false,
+    };
+    assert_eq!(crate::deserialize_from_reader(r), Ok(value.clone()));
+
+    assert_eq!(
+        Config::new()
+            .with_limit(100)
+            .deserialize_from_reader::<Vec<(String, u64)>>(bytes.as_slice()),
+        Err(E::LimitExceeded("size").e())
+    );
+}
+
+#[test]
+fn test_push_decoder() {
+    let values: Vec<Vec<(String, u64)>> = (0..20)
+        .map(|i| (0..i).map(|j| ("a".repeat(j), j as u64)).collect())
+        .collect();
+
+    // Push exactly as many bytes as requested, which must never go past the end of the value.
+    for v in &values {
+        let bytes = crate::serialize(v).unwrap();
+        let mut decoder = PushDecoder::new();
+        let mut pushed = 0;
+        loop {
+            match decoder.decode::<Vec<(String, u64)>>().unwrap() {
+                Progress::Done(decoded) => {
+                    assert_eq!(&decoded, v);
+                    break;
+                }
+                Progress::NeedMore(n) => {
+                    assert!(n > 0 && pushed + n <= bytes.len());
+                    decoder.push(&bytes[pushed..pushed + n]);
+                    pushed += n;
+                }
+            }
+        }
+        assert_eq!(pushed, bytes.len());
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    // Values back to back, pushed in uneven chunks.
+    let bytes: Vec<u8> = values
+        .iter()
+        .flat_map(|v| crate::serialize(v).unwrap())
+        .collect();
+    let mut decoder = PushDecoder::new();
+    let mut decoded: Vec<Vec<(String, u64)>> = vec![];
+    for chunk in bytes.chunks(7) {
+        decoder.push(chunk);
+        while let Progress::Done(v) = decoder.decode().unwrap() {
+            decoded.push(v);
+        }
+    }
+    assert_eq!(decoded, values);
+    assert_eq!(decoder.pending(), 0);
+
+    let mut decoder = PushDecoder::with_config(Config::new().with_limit(4));
+    decoder.push(&[0; 4]);
+    assert_eq!(decoder.decode::<u64>(), Err(E::LimitExceeded("size").e()));
+
+    let mut decoder = PushDecoder::new();
+    decoder.push(&[0b10]);
+    assert_eq!(decoder.decode::<bool>(), Err(E::Invalid("padding").e()));
+
+    // A corrupt length of about 2^40 isn't buffered forever without a limit.
+    let mut decoder = PushDecoder::new();
+    decoder.push(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+    assert_eq!(
+        decoder.decode::<String>(),
+        Err(E::LimitExceeded("frame length").e())
+    );
+
+    let value = vec![1u8; 100];
+    let bytes = crate::serialize(&value).unwrap();
+    for (max, expected) in [
+        (bytes.len(), Ok(Progress::Done(value))),
+        (bytes.len() - 1, Err(E::LimitExceeded("frame length").e())),
+    ] {
+        let mut decoder = PushDecoder::new().with_max_frame_length(max);
+        decoder.push(&bytes);
+        assert_eq!(decoder.decode::<Vec<u8>>(), expected);
+    }
+}
+
+#[test]
+fn test_packer() {
+    let values: Vec<(bool, Option<u16>, String)> = (0..100)
+        .map(|i| {
+            (
+                i % 3 == 0,
+                (i % 5 != 0).then_some(i * 7),
+                "a".repeat(i as usize % 4),
+            )
+        })
+        .collect();
+
+    for config in [
+        Config::new(),
+        Config::new().with_aligned_strings(),
+        Config::new().with_varint_encoding(),
+    ] {
+        let mut packer = Packer::with_config(config.clone());
+        for v in &values {
+            packer.pack(v).unwrap();
+        }
+        let packed = packer.finish().to_vec();
+
+        let concatenated: usize = values
+            .iter()
+            .map(|v| config.serialize(v).unwrap().len())
+            .sum();
+        // Aligned strings are padded to a byte anyway.
+        if !config.aligned_strings {
+            assert!(packed.len() < concatenated);
+        }
+
+        let mut unpacker = Unpacker::with_config(&packed, config.clone());
+        for v in &values {
+            assert_eq!(
+                &unpacker.unpack::<(bool, Option<u16>, String)>().unwrap(),
+                v
+            );
+        }
+        unpacker.finish().unwrap();
+
+        let mut unpacker = Unpacker::with_config(&packed, config.clone());
+        unpacker.unpack::<(bool, Option<u16>, String)>().unwrap();
+        assert_eq!(unpacker.finish(), Err(E::ExpectedEof.e()));
+
+        let mut unpacker = Unpacker::with_config(&packed[..packed.len() - 1], config);
+        let result: crate::Result<Vec<_>> = values
+            .iter()
+            .map(|_| unpacker.unpack::<(bool, Option<u16>, String)>())
+            .collect();
+        assert_eq!(result, Err(E::Eof.e()));
+    }
+
+    // The limit applies to each value instead of all of them, the same way on both sides.
+    let config = Config::new().with_limit(20);
+    let mut packer = Packer::with_config(config.clone());
+    for s in ["a".repeat(15), "b".repeat(15)] {
+        packer.pack(&s).unwrap();
+    }
+    assert_eq!(
+        packer.pack(&"c".repeat(25)),
+        Err(E::LimitExceeded("size").e())
+    );
+    packer.clear();
+    for s in ["a".repeat(15), "b".repeat(15)] {
+        packer.pack(&s).unwrap();
+    }
+    let packed = packer.finish().to_vec();
+    assert!(packed.len() > 20);
+    let mut unpacker = Unpacker::with_config(&packed, config.clone());
+    assert_eq!(unpacker.unpack::<String>().unwrap(), "a".repeat(15));
+    assert_eq!(unpacker.unpack::<String>().unwrap(), "b".repeat(15));
+    unpacker.finish().unwrap();
+
+    let mut packer = Packer::new();
+    for s in ["a".repeat(15), "c".repeat(25)] {
+        packer.pack(&s).unwrap();
+    }
+    let packed = packer.finish().to_vec();
+    let mut unpacker = Unpacker::with_config(&packed, config.clone());
+    assert_eq!(unpacker.unpack::<String>().unwrap(), "a".repeat(15));
+    assert_eq!(
+        unpacker.unpack::<String>(),
+        Err(E::LimitExceeded("size").e())
+    );
+
+    // The limit stops reading before the bytes of a value that claims to be too long.
+    let packed = crate::serialize(&"a".repeat(1000)).unwrap();
+    let mut unpacker = Unpacker::with_config(&packed[..10], config);
+    assert_eq!(
+        unpacker.unpack::<String>(),
+        Err(E::LimitExceeded("size").e())
+    );
+
+    let mut packer = Packer::new();
+    packer.pack(&1u8).unwrap();
+    packer.clear();
+    packer.pack(&true).unwrap();
+    assert_eq!(packer.finish(), &[1]);
+
+    // The padding returned by finish isn't kept.
+    packer.pack(&true).unwrap();
+    assert_eq!(packer.finish(), &[0b11]);
+}
+
+#[test]
+fn test_deserialize_prefix() {
+    let bytes = crate::serialize(&(true, 5u32)).unwrap();
+    assert_eq!(
+        crate::deserialize_prefix::<(bool, u32)>(&bytes[..bytes.len() - 1]),
+        Err(E::Eof.e())
+    );
+    assert_eq!(
+        crate::deserialize_prefix::<bool>(&[0b11]),
+        Err(E::Invalid("padding").e())
+    );
+    assert_eq!(
+        crate::deserialize_prefix::<bool>(&[1, 0]),
+        Ok((true, &[0][..]))
+    );
+
+    // Values longer than the bytes tried first, with and without borrowing.
+    let strings: Vec<String> = [1, 300, 5000, 2].iter().map(|&n| "a".repeat(n)).collect();
+    for config in [Config::new(), Config::new().with_aligned_strings()] {
+        let mut bytes = vec![];
+        let mut starts = vec![];
+        for s in &strings {
+            starts.push(bytes.len());
+            bytes.extend(config.serialize(s).unwrap());
+        }
+        let mut rest = &bytes[..];
+        for s in &strings {
+            let (decoded, r) = config.deserialize_prefix::<String>(rest).unwrap();
+            assert_eq!(&decoded, s);
+            rest = r;
+        }
+        assert!(rest.is_empty());
+
+        if config.aligned_strings {
+            let (decoded, _) = config
+                .deserialize_prefix::<&str>(&bytes[starts[2]..])
+                .unwrap();
+            assert_eq!(decoded, strings[2]);
+        }
+        assert_eq!(
+            config.deserialize_prefix::<String>(&bytes[starts[2]..starts[3] - 1]),
+            Err(E::Eof.e())
+        );
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_codec() {
+    use crate::BitcodeCodec;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    let values: Vec<Vec<String>> = (0..100).map(|i| vec!["a".repeat(i); i % 7]).collect();
+
+    for config in [Config::new(), Config::new().with_varint_lengths()] {
+        // A small buffer makes frames arrive in pieces.
+        let (a, b) = tokio::io::duplex(16);
+        let mut sink = FramedWrite::new(a, BitcodeCodec::with_config(config.clone()));
+        let mut stream = FramedRead::new(b, BitcodeCodec::<Vec<String>>::with_config(config));
+
+        let send = async {
+            for v in values.clone() {
+                sink.send(v).await.unwrap();
+            }
+            sink.close().await.unwrap();
+        };
+        let receive = async {
+            let mut received = vec![];
+            while let Some(v) = stream.next().await {
+                received.push(v.unwrap());
+            }
+            received
+        };
+        let ((), received) = tokio::join!(send, receive);
+        assert_eq!(received, values);
+    }
+
+    let (a, b) = tokio::io::duplex(1024);
+    let mut sink = FramedWrite::new(a, BitcodeCodec::new());
+    let mut stream = FramedRead::new(
+        b,
+        BitcodeCodec::<String>::with_config(Config::new().with_limit(10)),
+    );
+    sink.send("a".repeat(11)).await.unwrap();
+    assert_eq!(
+        stream.next().await.unwrap(),
+        Err(E::LimitExceeded("size").e())
+    );
+
+    // A corrupt length of about 2^40 is rejected without a limit instead of being reserved.
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+    let mut codec = BitcodeCodec::<String>::new();
+    let mut src = BytesMut::from(&[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0][..]);
+    assert_eq!(
+        codec.decode(&mut src),
+        Err(E::LimitExceeded("frame length").e())
+    );
+
+    let mut dst = BytesMut::new();
+    codec.encode("a".repeat(100), &mut dst).unwrap();
+    let frame_len = crate::serialize(&"a".repeat(100)).unwrap().len();
+    let mut codec = BitcodeCodec::<String>::new().with_max_frame_length(frame_len - 1);
+    assert_eq!(
+        codec.decode(&mut dst.clone()),
+        Err(E::LimitExceeded("frame length").e())
+    );
+    let mut codec = BitcodeCodec::<String>::new().with_max_frame_length(frame_len);
+    assert_eq!(codec.decode(&mut dst), Ok(Some("a".repeat(100))));
+    assert!(dst.is_empty());
+
+    // The longest lengths fit in the bytes reserved for the prefix.
+    for config in [Config::new(), Config::new().with_varint_lengths()] {
+        let mut prefix = [0; crate::ser::MAX_LEN_PREFIX];
+        let len = crate::ser::serialize_len_prefix(usize::MAX - 1, &mut prefix, &config).unwrap();
+        assert_eq!(
+            crate::de::deserialize_len_prefix(&prefix[..len], &config),
+            Ok((usize::MAX - 1, len))
+        );
+    }
+}
+
+#[test]
+fn test_max_depth() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct List(Option<Box<List>>);
+
+    // The newtype and the option each add a level.
+    let mut list = List(None);
+    for _ in 0..10 {
+        list = List(Some(Box::new(list)));
+    }
+    let bytes = crate::serialize(&list).unwrap();
+
+    let config = Config::new().with_max_depth(21);
+    assert_eq!(config.serialize(&list).as_deref(), Ok(bytes.as_slice()));
+    assert_eq!(config.deserialize::<List>(&bytes).as_ref(), Ok(&list));
+
+    let config = Config::new().with_max_depth(20);
+    assert_eq!(config.serialize(&list), Err(E::LimitExceeded("depth").e()));
+    assert_eq!(
+        config.deserialize::<List>(&bytes),
+        Err(E::LimitExceeded("depth").e())
+    );
+
+    // All 1 bits are an endless chain of Some, which would overflow the stack without a limit.
+    let hostile = vec![u8::MAX; 100000];
+    let config = Config::new().with_max_depth(100);
+    assert_eq!(
+        config.deserialize::<List>(&hostile),
+        Err(E::LimitExceeded("depth").e())
+    );
+}
+
+#[test]
+fn test_columnar_layout() {
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum Kind {
+        A,
+        B(u16),
+        C { name: String, parent: Option<u32> },
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: u32,
+        kind: Kind,
+        tags: Vec<(bool, i8)>,
+        scores: HashMap<u8, f32>,
+    }
+
+    let items: Vec<Item> = (0..50)
+        .map(|i| Item {
+            id: i * 1000,
+            kind: match i % 3 {
+                0 => Kind::A,
+                1 => Kind::B(i as u16),
+                _ => Kind::C {
+                    name: i.to_string(),
+                    parent: (i % 2 == 0).then_some(i / 2),
+                },
+            },
+            tags: vec![(i % 5 == 0, -(i as i8)); i as usize % 4],
+            scores: (0..i as u8 % 3).map(|k| (k, k as f32 / 2.0)).collect(),
+        })
+        .collect();
+    the_same_inner(items.clone());
+    the_same_inner(HashMap::from([(1u8, items.clone()), (2, vec![])]));
+
+    // The second field of a key and the value don't share a column.
+    let map: HashMap<(u8, String), u8> = (0..10).map(|i| ((i, i.to_string()), i * 2)).collect();
+    the_same_inner(map.clone());
+    let bytes = Config::new()
+        .with_columnar_layout()
+        .serialize(&map)
+        .unwrap();
+    let mut r = DeVec::from_inner(&bytes);
+    assert_eq!(crate::de::read_gamma(&mut r), Ok(map.len()));
+    // The entries, the keys, the 2 fields of the keys (and the empty slot after them) and the
+    // values, which would be 1 fewer if the values shared a column with the second fields.
+    assert_eq!(crate::de::read_gamma(&mut r), Ok(6));
+
+    let config = Config::new().with_columnar_layout();
+    let bytes = config.serialize(&items).unwrap();
+    assert_ne!(bytes, crate::serialize(&items).unwrap());
+    assert!(crate::deserialize::<Vec<Item>>(&bytes).is_err());
+
+    // Columns must be read to the end.
+    let bytes = config.serialize(&vec![(1u8, 2u8)]).unwrap();
+    assert_eq!(
+        config.deserialize::<Vec<(u8, u8, ())>>(&bytes),
+        Ok(vec![(1, 2, ())])
+    );
+    assert_eq!(
+        config.deserialize::<Vec<(u8,)>>(&bytes),
+        Err(E::ExpectedEof.e())
+    );
+
+    // Hides the length of the sequence from the serializer.
+    struct Unknown(Vec<u8>);
+
+    impl Serialize for Unknown {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().filter(|&v| v % 2 == 1))
+        }
+    }
+
+    assert_eq!(
+        config.serialize(&Unknown(vec![1, 2, 3])),
+        Err(E::NotSupported("unknown len with columnar layout").e())
+    );
+}
+
 #[test]
 #[cfg_attr(debug_assertions, ignore)]
 fn test_chars() {
@@ -129,6 +1923,9 @@ fn test_numbers() {
     the_same(5u64);
     the_same(u64::MAX - 5);
     the_same(u64::MAX);
+    the_same(5u128);
+    the_same(u128::MAX - 5);
+    the_same(u128::MAX);
     the_same(5usize);
     // signed positive
     the_same(5i8);
@@ -145,6 +1942,9 @@ fn test_numbers() {
     the_same(i64::MAX - 5);
     the_same(i64::MIN);
     the_same(i64::MIN + 5);
+    the_same(-5i128);
+    the_same(i128::MAX);
+    the_same(i128::MIN);
     the_same(-5isize);
     // floating
     the_same(-100f32);
@@ -236,6 +2036,7 @@ fn test_option() {
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_enum() {
     #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
     enum TestEnum {